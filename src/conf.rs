@@ -0,0 +1,132 @@
+use crate::PodmanCtx;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONTAINERS_CONF_ENV: &str = "CONTAINERS_CONF";
+pub const CONTAINERS_STORAGE_CONF_ENV: &str = "CONTAINERS_STORAGE_CONF";
+
+const CONTAINERS_CONF_FILE: &str = "containers.conf";
+const STORAGE_CONF_FILE: &str = "storage.conf";
+const HEADER: &str = "# Generated by sarus-suite-podman-driver from a PodmanCtx, do not edit.\n";
+
+// Paths of the configuration files materialized from a PodmanCtx.
+// Exporting `env()` in a shell makes a manually run `podman` see the same configuration as the
+// driver.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfFiles {
+    pub containers_conf: PathBuf,
+    pub storage_conf: PathBuf,
+}
+
+impl ConfFiles {
+    pub fn env(&self) -> [(&'static str, &Path); 2] {
+        [
+            (CONTAINERS_CONF_ENV, self.containers_conf.as_path()),
+            (CONTAINERS_STORAGE_CONF_ENV, self.storage_conf.as_path()),
+        ]
+    }
+}
+
+impl PodmanCtx {
+    // Render the storage.conf equivalent of the storage CLI options set by the driver.
+    // The module is not part of it: podman only loads modules through `--module`.
+    pub fn storage_conf(&self) -> anyhow::Result<String> {
+        let mut conf = String::from(HEADER);
+        conf.push_str("[storage]\n");
+        conf.push_str("driver = \"overlay\"\n");
+        if let Some(graphroot) = &self.graphroot {
+            writeln!(conf, "graphroot = {}", toml_str(graphroot.as_os_str())?)?;
+        }
+        if let Some(runroot) = &self.runroot {
+            writeln!(conf, "runroot = {}", toml_str(runroot.as_os_str())?)?;
+        }
+
+        conf.push_str("\n[storage.options]\n");
+        let stores = self
+            .ro_stores
+            .iter()
+            .map(|s| toml_str(s.as_os_str()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        writeln!(conf, "additionalimagestores = [{}]", stores.join(", "))?;
+
+        if let Some(mp) = &self.parallax_mount_program {
+            conf.push_str("\n[storage.options.overlay]\n");
+            writeln!(conf, "mount_program = {}", toml_str(mp.as_os_str())?)?;
+        }
+
+        Ok(conf)
+    }
+
+    // Render a containers.conf carrying `podman_env` as engine environment, so that helpers
+    // spawned by podman (e.g. the parallax mount program) get it even outside of the driver.
+    pub fn containers_conf(&self) -> anyhow::Result<String> {
+        let mut conf = String::from(HEADER);
+        conf.push_str("[engine]\n");
+
+        let mut env = Vec::new();
+        if let Some(envs) = &self.podman_env {
+            for (k, v) in envs {
+                // Pointing at ourselves would only matter to podman itself, not to its helpers
+                if k == CONTAINERS_CONF_ENV || k == CONTAINERS_STORAGE_CONF_ENV {
+                    continue;
+                }
+                let mut kv = k.clone();
+                kv.push("=");
+                kv.push(v);
+                env.push(toml_str(&kv)?);
+            }
+        }
+        // HashMap iteration order is random: sort to keep the output stable
+        env.sort();
+        writeln!(conf, "env = [{}]", env.join(", "))?;
+
+        Ok(conf)
+    }
+
+    // Write containers.conf and storage.conf into `dir` (created if missing).
+    pub fn write_conf_files(&self, dir: &Path) -> anyhow::Result<ConfFiles> {
+        fs::create_dir_all(dir)?;
+
+        let files = ConfFiles {
+            containers_conf: dir.join(CONTAINERS_CONF_FILE),
+            storage_conf: dir.join(STORAGE_CONF_FILE),
+        };
+        fs::write(&files.containers_conf, self.containers_conf()?)?;
+        fs::write(&files.storage_conf, self.storage_conf()?)?;
+        Ok(files)
+    }
+
+    // Write the configuration files into `dir` and point every podman started from this
+    // context at them through CONTAINERS_CONF and CONTAINERS_STORAGE_CONF, e.g.:
+    // let p_ctx = PodmanCtx { ... }.with_conf_files(&job_dir.join("podman"))?;
+    pub fn with_conf_files(self, dir: &Path) -> anyhow::Result<Self> {
+        let files = self.write_conf_files(dir)?;
+        let [(conf_key, conf), (storage_key, storage)] = files.env();
+        Ok(self.with_env(conf_key, conf).with_env(storage_key, storage))
+    }
+}
+
+// TOML basic string. Paths and env vars are OS strings, but TOML files must be UTF-8.
+fn toml_str(val: &OsStr) -> anyhow::Result<String> {
+    let Some(val) = val.to_str() else {
+        anyhow::bail!("cannot write non UTF-8 value {val:?} in a TOML file");
+    };
+
+    let mut out = String::with_capacity(val.len() + 2);
+    out.push('"');
+    for c in val.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => write!(out, "\\u{:04X}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(out)
+}
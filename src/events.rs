@@ -0,0 +1,269 @@
+// Container and image events as they happen, e.g. to react to the death of a container
+// without polling inspect
+use crate::{PodmanCtx, commands};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::process::{Child, ChildStdout, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Start,
+    // The container exited, see `Event::exit_code`
+    Died,
+    Oom,
+    Remove,
+    Pull,
+    // Any other status reported by podman, e.g. "create" or "cleanup"
+    Other(String),
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Start => "start",
+            Self::Died => "died",
+            Self::Oom => "oom",
+            Self::Remove => "remove",
+            Self::Pull => "pull",
+            Self::Other(status) => status,
+        }
+    }
+
+    fn from_status(status: &str) -> Self {
+        match status {
+            "start" => Self::Start,
+            "died" => Self::Died,
+            "oom" => Self::Oom,
+            "remove" => Self::Remove,
+            "pull" => Self::Pull,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    // Container ID, or image ID for image events
+    pub id: String,
+    // Container name, or image reference for image events
+    pub name: String,
+    pub image: String,
+    pub time: Option<SystemTime>,
+    // Died events only
+    pub exit_code: Option<i32>,
+    // Labels of the container among others
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EventsOptions {
+    // Containers by name or ID, any of them
+    pub containers: Vec<String>,
+    // All of them
+    pub labels: BTreeMap<String, String>,
+    // Any of them, all events if empty
+    pub events: Vec<EventKind>,
+    // Timestamp or duration before now, as taken by podman, e.g. "2025-06-01T10:00:00Z", "10m".
+    // Past events are replayed first.
+    pub since: Option<String>,
+    // The stream ends once reached, otherwise it never does
+    pub until: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawEvent {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(rename = "Name", default)]
+    name: String,
+    #[serde(rename = "Image", default)]
+    image: String,
+    #[serde(rename = "Status")]
+    status: String,
+    #[serde(rename = "timeNano")]
+    time_nano: Option<u64>,
+    #[serde(rename = "ContainerExitCode")]
+    exit_code: Option<i32>,
+    #[serde(rename = "Attributes", default)]
+    attributes: BTreeMap<String, String>,
+}
+
+impl From<RawEvent> for Event {
+    fn from(raw: RawEvent) -> Self {
+        let kind = EventKind::from_status(&raw.status);
+        Event {
+            exit_code: raw.exit_code.filter(|_| kind == EventKind::Died),
+            kind,
+            id: raw.id,
+            name: raw.name,
+            image: raw.image,
+            time: raw
+                .time_nano
+                .map(|nanos| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos)),
+            attributes: raw.attributes,
+        }
+    }
+}
+
+pub fn events(
+    options: &EventsOptions,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<EventStream> {
+    let mut child = commands::events(options, podman_ctx)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    // Read aside, podman could block on a full pipe otherwise
+    let stderr = thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    });
+    Ok(EventStream {
+        child,
+        lines: BufReader::new(stdout).lines(),
+        stderr: Some(stderr),
+        ended: false,
+    })
+}
+
+// Events in the order podman reports them, until `until` is reached or the stream is dropped.
+// A failure of podman, e.g. an invalid filter, comes as a last error.
+pub struct EventStream {
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    ended: bool,
+}
+
+impl EventStream {
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let status = self.child.wait()?;
+        let stderr = self
+            .stderr
+            .take()
+            .map(|reader| reader.join().unwrap_or_default())
+            .unwrap_or_default();
+        if !status.success() {
+            let stderr = String::from_utf8_lossy(&stderr);
+            anyhow::bail!("podman events exited with {status}: {}", stderr.trim());
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = anyhow::Result<Event>;
+
+    fn next(&mut self) -> Option<anyhow::Result<Event>> {
+        if self.ended {
+            return None;
+        }
+        loop {
+            match self.lines.next() {
+                // Skip what we cannot make sense of rather than ending the stream
+                Some(Ok(line)) => {
+                    if let Ok(raw) = serde_json::from_str::<RawEvent>(&line) {
+                        return Some(Ok(raw.into()));
+                    }
+                }
+                Some(Err(e)) => {
+                    self.ended = true;
+                    return Some(Err(
+                        anyhow::Error::new(e).context("cannot read podman events")
+                    ));
+                }
+                None => {
+                    self.ended = true;
+                    return self.finish().err().map(Err);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(reader) = self.stderr.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+
+    #[test]
+    fn test_events() -> anyhow::Result<()> {
+        let podman = FakePodman::new(
+            "events",
+            r#"case "$*" in
+    *container=gone*)
+        echo "Error: no container with name or ID \"gone\" found: no such container" >&2
+        exit 125 ;;
+esac
+cat <<'EOF'
+{"ID":"aaaa1111","Image":"alpine","Name":"rank0","Status":"start","timeNano":1748772000000000000,"Type":"container","Attributes":{"sarus-suite.slurm-job-id":"42"}}
+not json
+{"ID":"aaaa1111","Image":"alpine","Name":"rank0","Status":"oom","Type":"container"}
+{"ID":"aaaa1111","Image":"alpine","Name":"rank0","Status":"died","ContainerExitCode":137,"Type":"container"}
+{"ID":"bbbb2222","Name":"docker.io/library/ubuntu:24.04","Status":"pull","Type":"image"}
+{"ID":"aaaa1111","Image":"alpine","Name":"rank0","Status":"cleanup","Type":"container"}
+EOF
+"#,
+        );
+        let p_ctx = podman.ctx();
+
+        let options = EventsOptions {
+            containers: vec![String::from("rank0")],
+            labels: BTreeMap::from([(
+                String::from("sarus-suite.slurm-job-id"),
+                String::from("42"),
+            )]),
+            events: vec![EventKind::Died, EventKind::Oom],
+            since: Some(String::from("10m")),
+            ..Default::default()
+        };
+        let events: Vec<Event> = events(&options, Some(&p_ctx))?.collect::<anyhow::Result<_>>()?;
+        let kinds: Vec<&str> = events.iter().map(|e| e.kind.as_str()).collect();
+        assert_eq!(kinds, ["start", "oom", "died", "pull", "cleanup"]);
+        assert_eq!(
+            events[0].time,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_748_772_000))
+        );
+        assert_eq!(events[0].attributes["sarus-suite.slurm-job-id"], "42");
+        assert_eq!(events[0].exit_code, None);
+        assert_eq!(events[2].exit_code, Some(137));
+        assert_eq!(events[3].name, "docker.io/library/ubuntu:24.04");
+        assert_eq!(events[4].kind, EventKind::Other(String::from("cleanup")));
+
+        // A failure of podman rather than an empty stream
+        let gone = EventsOptions {
+            containers: vec![String::from("gone")],
+            ..Default::default()
+        };
+        let mut stream = super::events(&gone, Some(&p_ctx))?;
+        let err = stream.next().unwrap().unwrap_err();
+        assert!(err.to_string().ends_with("no such container"));
+        assert!(stream.next().is_none());
+
+        assert_eq!(
+            podman.calls()[0],
+            "events --format json --filter container=rank0 \
+             --filter label=sarus-suite.slurm-job-id=42 --filter event=died \
+             --filter event=oom --since 10m"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+// Why a container exited, telling failures of the application from OOM kills, signals and
+// failures of podman itself, which all end up as the exit status of `podman run`
+use crate::guard::{ContainerGuard, GuardOptions};
+use crate::{ContainerCtx, PodmanCtx, checked_output, commands, naming, owned_args};
+use raster::EDF;
+use serde::Deserialize;
+use std::ffi::OsStr;
+use std::process::{ExitStatus, Output};
+
+// Podman exits with this code when it fails, rather than the container
+const ENGINE_ERROR_CODE: i32 = 125;
+// Podman reports containers that never started as started at the zero time
+const NEVER_STARTED: &str = "0001-01-01T00:00:00Z";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    Success,
+    // The application exited with this non-zero code
+    Failure(i32),
+    // Killed by this signal, e.g. 9 for SIGKILL, 11 for SIGSEGV
+    Signal(i32),
+    Oom,
+    // The container could not be run, with the error of podman if known
+    Engine(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct ContainerExit {
+    // Of `podman start`, or of `podman create` if the container could not be created
+    pub status: ExitStatus,
+    pub reason: ExitReason,
+    // From inspecting the container, None if there was no container to inspect
+    pub exit_code: Option<i32>,
+    pub oom_killed: bool,
+    pub error: Option<String>,
+    // RFC3339 timestamp
+    pub finished_at: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct State {
+    exit_code: i32,
+    #[serde(rename = "OOMKilled")]
+    oom_killed: bool,
+    error: String,
+    started_at: String,
+    finished_at: String,
+}
+
+impl ContainerExit {
+    fn not_created(create: Output) -> Self {
+        let status = create.status;
+        let stderr = String::from_utf8_lossy(&create.stderr);
+        let error = match stderr.trim() {
+            "" => format!("container was not created, podman exited with {status}"),
+            stderr => stderr.to_string(),
+        };
+        Self {
+            status,
+            reason: ExitReason::Engine(error),
+            exit_code: None,
+            oom_killed: false,
+            error: None,
+            finished_at: None,
+        }
+    }
+
+    fn new(status: ExitStatus, state: State) -> Self {
+        let never_started =
+            status.code() == Some(ENGINE_ERROR_CODE) && state.started_at.starts_with(NEVER_STARTED);
+        let reason = if !state.error.is_empty() {
+            ExitReason::Engine(state.error.clone())
+        } else if never_started {
+            ExitReason::Engine(format!(
+                "container never started, podman exited with {status}"
+            ))
+        } else if state.oom_killed {
+            ExitReason::Oom
+        } else {
+            match state.exit_code {
+                0 => ExitReason::Success,
+                // As reported by the runtime, indistinguishable from the application exiting
+                // with such a code itself
+                code @ 129..=255 => ExitReason::Signal(code - 128),
+                code => ExitReason::Failure(code),
+            }
+        };
+        Self {
+            status,
+            reason,
+            exit_code: Some(state.exit_code),
+            oom_killed: state.oom_killed,
+            error: Some(state.error).filter(|e| !e.is_empty()),
+            finished_at: Some(state.finished_at).filter(|t| !t.is_empty()),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.reason == ExitReason::Success
+    }
+}
+
+// Run the container attached until it exits, then classify its exit. Unlike run_from_edf(), the
+// container is created without --rm so that it can still be inspected, and is removed
+// afterwards, by ID so that a container of the same name is never touched.
+pub fn run_from_edf_exit<I, S>(
+    edf: &EDF,
+    p_ctx: Option<&PodmanCtx>,
+    c_ctx: &ContainerCtx,
+    container_cmd: I,
+) -> anyhow::Result<ContainerExit>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    anyhow::ensure!(
+        !c_ctx.detach,
+        "container must be attached to report its exit"
+    );
+
+    c_ctx.validate()?;
+    let container_cmd = owned_args(container_cmd);
+    let (output, _) = naming::launch_named(c_ctx, |c_ctx| {
+        commands::create_from_edf_kept(edf, p_ctx, c_ctx, &container_cmd).output()
+    })?;
+    // Nothing to inspect nor to remove
+    if !output.status.success() {
+        return Ok(ContainerExit::not_created(output));
+    }
+    let id = str::from_utf8(&output.stdout)?.trim();
+    anyhow::ensure!(!id.is_empty(), "podman create printed no container ID");
+
+    // Removes the container on errors too
+    let guard = ContainerGuard::new(
+        id,
+        p_ctx,
+        GuardOptions {
+            force: true,
+            ..Default::default()
+        },
+    );
+    let status = commands::start(guard.id(), true, c_ctx.interactive, p_ctx).status()?;
+    let output = checked_output(
+        &mut commands::inspect(guard.id(), Some("{{json .State}}"), p_ctx),
+        "podman inspect",
+    )?;
+    let state = serde_json::from_slice::<State>(&output.stdout)?;
+    guard.cleanup()?;
+    Ok(ContainerExit::new(status, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{FakePodman, test_edf};
+    use std::fs;
+
+    #[test]
+    fn test_run_from_edf_exit() -> anyhow::Result<()> {
+        // Creates fail with the error in create_error if any, starts exit with the code in
+        // run_code, inspect prints state
+        let podman = FakePodman::new(
+            "exit",
+            r#"case "$*" in
+    create*)
+        if [ -f "$test_dir/create_error" ]; then
+            cat "$test_dir/create_error" >&2
+            exit 125
+        fi
+        echo "cccc3333" ;;
+    start*) exit $(cat "$test_dir/run_code") ;;
+    *inspect*) cat "$test_dir/state" ;;
+esac
+"#,
+        );
+        let p_ctx = podman.ctx();
+        let run_code = podman.dir().join("run_code");
+        let state = podman.dir().join("state");
+        let create_error = podman.dir().join("create_error");
+        let edf = test_edf("alpine.toml");
+        let c_ctx = ContainerCtx {
+            name: String::from("rank0"),
+            ..Default::default()
+        };
+
+        let started = r#""StartedAt":"2025-06-01T10:00:00Z","FinishedAt":"2025-06-01T10:05:00Z""#;
+        let cases = [
+            (
+                "0",
+                format!(r#"{{"ExitCode":0,{started}}}"#),
+                ExitReason::Success,
+            ),
+            (
+                "3",
+                format!(r#"{{"ExitCode":3,{started}}}"#),
+                ExitReason::Failure(3),
+            ),
+            (
+                "139",
+                format!(r#"{{"ExitCode":139,{started}}}"#),
+                ExitReason::Signal(11),
+            ),
+            (
+                "137",
+                format!(r#"{{"ExitCode":137,"OOMKilled":true,{started}}}"#),
+                ExitReason::Oom,
+            ),
+            (
+                "125",
+                format!(r#"{{"ExitCode":0,"StartedAt":"{NEVER_STARTED}","FinishedAt":""}}"#),
+                ExitReason::Engine(String::from(
+                    "container never started, podman exited with exit status: 125",
+                )),
+            ),
+        ];
+        for (code, inspected, reason) in cases {
+            fs::write(&run_code, code)?;
+            fs::write(&state, inspected)?;
+            let exit = run_from_edf_exit(&edf, Some(&p_ctx), &c_ctx, ["true"])?;
+            assert_eq!(exit.reason, reason);
+            assert_eq!(exit.status.code(), code.parse().ok());
+        }
+
+        // Inspected, but never finished
+        let exit = run_from_edf_exit(&edf, Some(&p_ctx), &c_ctx, ["true"])?;
+        assert_eq!(exit.exit_code, Some(0));
+        assert_eq!(exit.finished_at, None);
+
+        // Unreadable state, still removed
+        fs::write(&state, "{")?;
+        assert!(run_from_edf_exit(&edf, Some(&p_ctx), &c_ctx, ["true"]).is_err());
+
+        // Name taken by another container, which is left alone
+        fs::write(
+            &create_error,
+            "Error: creating container storage: the container name \"rank0\" is already in use",
+        )?;
+        let exit = run_from_edf_exit(&edf, Some(&p_ctx), &c_ctx, ["true"])?;
+        assert_eq!(
+            exit.reason,
+            ExitReason::Engine(String::from(
+                "Error: creating container storage: the container name \"rank0\" is already in use"
+            ))
+        );
+        assert_eq!(exit.status.code(), Some(125));
+        assert_eq!(exit.exit_code, None);
+
+        let calls = podman.calls();
+        assert!(calls[0].starts_with("create ") && calls[0].contains(" --name rank0 "));
+        assert!(!calls[0].contains("--rm"));
+        assert_eq!(calls[1], "start --attach cccc3333");
+        assert_eq!(
+            calls[2],
+            "--log-level=error inspect -f {{json .State}} cccc3333"
+        );
+        assert_eq!(calls[3], "rm --force --ignore cccc3333");
+        assert_eq!(calls.len(), 7 * 4 + 1);
+        assert_eq!(calls[7 * 4 - 1], "rm --force --ignore cccc3333");
+        assert!(calls[7 * 4].starts_with("create "));
+
+        let detached = ContainerCtx {
+            name: String::from("rank0"),
+            detach: true,
+            ..Default::default()
+        };
+        assert!(run_from_edf_exit(&edf, Some(&p_ctx), &detached, ["true"]).is_err());
+        Ok(())
+    }
+}
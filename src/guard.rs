@@ -0,0 +1,189 @@
+// Scoped ownership of a detached container, which is stopped and removed when the owner goes
+// away, including on early returns and panics
+use crate::naming;
+use crate::{
+    ContainerCtx, PodmanCtx, RmOptions, StopOptions, checked, checked_output, commands, owned_args,
+};
+use raster::EDF;
+use std::ffi::OsStr;
+
+#[derive(Clone, Debug, Default)]
+pub struct GuardOptions {
+    // Seconds granted to the container to stop before it is killed, podman's default if None
+    pub stop_timeout: Option<u32>,
+    // Remove the container right away, killing it after `stop_timeout` if still running
+    pub force: bool,
+}
+
+pub struct ContainerGuard<'a> {
+    id: String,
+    name: String,
+    podman_ctx: Option<&'a PodmanCtx>,
+    options: GuardOptions,
+    armed: bool,
+}
+
+impl<'a> ContainerGuard<'a> {
+    // `id` may also be the container name
+    pub fn new(
+        id: impl Into<String>,
+        podman_ctx: Option<&'a PodmanCtx>,
+        options: GuardOptions,
+    ) -> Self {
+        let id = id.into();
+        Self {
+            name: id.clone(),
+            id,
+            podman_ctx,
+            options,
+            armed: true,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // The name the container was launched under, the ID if unknown
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Keep the container running past the guard and return its ID
+    pub fn disarm(mut self) -> String {
+        self.armed = false;
+        std::mem::take(&mut self.id)
+    }
+
+    // Stop and remove now, reporting failures that dropping the guard would ignore
+    pub fn cleanup(mut self) -> anyhow::Result<()> {
+        self.armed = false;
+        self.stop_and_rm()
+    }
+
+    fn stop_and_rm(&self) -> anyhow::Result<()> {
+        // Containers run with --rm may be gone once stopped, so missing ones are ignored
+        let GuardOptions {
+            stop_timeout,
+            force,
+        } = self.options;
+        if !force {
+            let stop = StopOptions {
+                timeout: stop_timeout,
+                signal: None,
+                ignore: true,
+            };
+            checked_output(
+                &mut commands::stop_with(&[&self.id], &stop, self.podman_ctx),
+                "podman stop",
+            )?;
+        }
+        let rm = RmOptions {
+            force,
+            ignore: true,
+            volumes: false,
+            timeout: stop_timeout.filter(|_| force),
+        };
+        checked_output(
+            &mut commands::rm_with(&[&self.id], &rm, self.podman_ctx),
+            "podman rm",
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for ContainerGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.stop_and_rm();
+        }
+    }
+}
+
+// Launch a detached container (`ContainerCtx::detach` must be set) and guard it
+pub fn run_from_edf_guarded<'a, I, S>(
+    edf: &EDF,
+    p_ctx: Option<&'a PodmanCtx>,
+    c_ctx: &ContainerCtx,
+    container_cmd: I,
+    options: GuardOptions,
+) -> anyhow::Result<ContainerGuard<'a>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    anyhow::ensure!(
+        c_ctx.detach,
+        "container {} must be detached to be guarded",
+        c_ctx.name
+    );
+
+    c_ctx.validate()?;
+    let container_cmd = owned_args(container_cmd);
+    let (output, name) = naming::launch_named(c_ctx, |c_ctx| {
+        commands::run_from_edf(edf, p_ctx, c_ctx, &container_cmd).output()
+    })?;
+    let output = checked(output, "podman run")?;
+    // A detached run prints the container ID
+    let id = str::from_utf8(&output.stdout)?.trim();
+    let id = if id.is_empty() { &name } else { id };
+    let mut guard = ContainerGuard::new(id, p_ctx, options);
+    guard.name = name;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{FakePodman, test_edf};
+
+    #[test]
+    fn test_container_guard() -> anyhow::Result<()> {
+        let podman = FakePodman::new("guard", "");
+        let p_ctx = podman.ctx();
+        let timeout = GuardOptions {
+            stop_timeout: Some(5),
+            force: false,
+        };
+
+        let result = std::panic::catch_unwind(|| {
+            let _guard = ContainerGuard::new("aaaa1111", Some(&p_ctx), timeout.clone());
+            panic!("job step failed");
+        });
+        assert!(result.is_err());
+
+        let kept = ContainerGuard::new("bbbb2222", Some(&p_ctx), timeout.clone());
+        assert_eq!(kept.disarm(), "bbbb2222");
+
+        let forced = GuardOptions {
+            stop_timeout: None,
+            force: true,
+        };
+        ContainerGuard::new("cccc3333", Some(&p_ctx), forced).cleanup()?;
+
+        assert_eq!(
+            podman.calls(),
+            [
+                "stop --ignore --time 5 aaaa1111",
+                "rm --ignore aaaa1111",
+                "rm --force --ignore cccc3333",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_guarded_run_requires_detach() {
+        let edf = test_edf("alpine.toml");
+        let c_ctx = ContainerCtx {
+            name: String::from("sarus_guard_test"),
+            detach: false,
+            ..Default::default()
+        };
+
+        let err = run_from_edf_guarded(&edf, None, &c_ctx, ["true"], GuardOptions::default())
+            .err()
+            .expect("attached run guarded");
+        assert!(err.to_string().contains("must be detached"));
+    }
+}
@@ -0,0 +1,174 @@
+// User and groups the container process runs as. Unset settings keep the image's user.
+use anyhow::Context;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub user: Option<User>,
+    pub group_add: Vec<Group>,
+    // Template of the /etc/passwd entry added for `user`,
+    // e.g. "$USERNAME:x:$UID:$GID::$HOME:/bin/sh"
+    pub passwd_entry: Option<String>,
+    // How the IDs above are mapped to the host
+    pub userns: Option<UserNsMode>,
+}
+
+impl Identity {
+    // The UID, GID and supplementary groups of the caller, so that files written to bind mounts
+    // keep its ownership. When rootless, the caller is mapped to the same IDs in the container.
+    pub fn invoking_user() -> anyhow::Result<Self> {
+        let (uid, gid) = invoking_ids()?;
+        Ok(Self::of_ids(uid, gid))
+    }
+
+    // Podman only takes keep-groups and keep-id from rootless callers
+    fn of_ids(uid: u32, gid: u32) -> Self {
+        let rootless = uid != 0;
+        Self {
+            user: Some(User::Id {
+                uid,
+                gid: Some(gid),
+            }),
+            group_add: if rootless {
+                vec![Group::KeepGroups]
+            } else {
+                Vec::new()
+            },
+            passwd_entry: None,
+            userns: rootless.then(UserNsMode::keep_id),
+        }
+    }
+
+    // Reject what podman would only refuse once it runs
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        if self.group_add.contains(&Group::KeepGroups) && self.group_add.len() > 1 {
+            anyhow::bail!("keep-groups cannot be combined with other supplementary groups");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum User {
+    Id { uid: u32, gid: Option<u32> },
+    Name { user: String, group: Option<String> },
+}
+
+impl User {
+    // "<user>[:<group>]" as taken by `--user`
+    pub fn to_arg(&self) -> String {
+        match self {
+            Self::Id { uid, gid: None } => uid.to_string(),
+            Self::Id {
+                uid,
+                gid: Some(gid),
+            } => format!("{uid}:{gid}"),
+            Self::Name { user, group: None } => user.clone(),
+            Self::Name {
+                user,
+                group: Some(group),
+            } => format!("{user}:{group}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Group {
+    Id(u32),
+    Name(String),
+    // The supplementary groups of the caller, e.g. for group-owned project directories.
+    // Rootless only, and exclusive with other groups.
+    KeepGroups,
+}
+
+impl Group {
+    pub fn to_arg(&self) -> String {
+        match self {
+            Self::Id(gid) => gid.to_string(),
+            Self::Name(name) => name.clone(),
+            Self::KeepGroups => String::from("keep-groups"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UserNsMode {
+    Host,
+    // Map the caller's UID and GID to the same IDs in the container, or to the given ones
+    KeepId { uid: Option<u32>, gid: Option<u32> },
+    // Leave the caller's own UID and GID unmapped (rootless only)
+    NoMap,
+    Auto,
+    Private,
+    Path(PathBuf),
+}
+
+impl UserNsMode {
+    pub fn keep_id() -> Self {
+        Self::KeepId {
+            uid: None,
+            gid: None,
+        }
+    }
+
+    pub fn to_arg(&self) -> String {
+        match self {
+            Self::Host => String::from("host"),
+            Self::KeepId { uid, gid } => {
+                let mapping: Vec<String> = [("uid", uid), ("gid", gid)]
+                    .into_iter()
+                    .filter_map(|(key, id)| id.map(|id| format!("{key}={id}")))
+                    .collect();
+                if mapping.is_empty() {
+                    String::from("keep-id")
+                } else {
+                    format!("keep-id:{}", mapping.join(","))
+                }
+            }
+            Self::NoMap => String::from("nomap"),
+            Self::Auto => String::from("auto"),
+            Self::Private => String::from("private"),
+            Self::Path(path) => format!("ns:{}", path.display()),
+        }
+    }
+}
+
+// The owner of /proc/self is the effective UID and GID of the process
+fn invoking_ids() -> anyhow::Result<(u32, u32)> {
+    let proc_self = fs::metadata("/proc/self").context("cannot determine the invoking user")?;
+    Ok((proc_self.uid(), proc_self.gid()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoking_user() {
+        let rootless = Identity::of_ids(1000, 100);
+        assert_eq!(
+            rootless.user,
+            Some(User::Id {
+                uid: 1000,
+                gid: Some(100)
+            })
+        );
+        assert_eq!(rootless.group_add, [Group::KeepGroups]);
+        assert_eq!(rootless.userns, Some(UserNsMode::keep_id()));
+        assert!(rootless.check().is_ok());
+
+        // Podman refuses keep-groups for rootful runs
+        let root = Identity::of_ids(0, 0);
+        assert_eq!(
+            root.user,
+            Some(User::Id {
+                uid: 0,
+                gid: Some(0)
+            })
+        );
+        assert!(root.group_add.is_empty());
+        assert_eq!(root.userns, None);
+    }
+}
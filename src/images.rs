@@ -0,0 +1,80 @@
+use crate::{PodmanCtx, checked_output, commands, storage};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+// Where podman found an image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImageStore {
+    Graphroot,
+    ReadOnly(PathBuf),
+    // Read-only store that is not listed in PodmanCtx::ro_stores, e.g. one from a storage.conf
+    UnknownReadOnly,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageSummary {
+    pub id: String,
+    pub names: Vec<String>,
+    pub digest: String,
+    pub size: u64,
+    pub created: i64,
+    pub store: ImageStore,
+}
+
+// Subset of the entries printed by `podman images --format json`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PodmanImage {
+    id: String,
+    #[serde(default)]
+    names: Option<Vec<String>>,
+    #[serde(default)]
+    digest: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    created: i64,
+    #[serde(default)]
+    read_only: bool,
+}
+
+pub fn list_images(podman_ctx: Option<&PodmanCtx>) -> anyhow::Result<Vec<ImageSummary>> {
+    let output = checked_output(&mut commands::images_json(podman_ctx), "podman images")?;
+    let images: Vec<PodmanImage> = serde_json::from_slice(&output.stdout)?;
+
+    // Podman only tells whether an image is read-only: match its ID against the metadata of
+    // every store. Stores are searched in order, like containers/storage does.
+    let mut ro_ids: Vec<(PathBuf, HashSet<String>)> = Vec::new();
+    for store in podman_ctx.iter().flat_map(|ctx| &ctx.ro_stores) {
+        let ids = storage::read_images(store)?.into_iter().map(|img| img.id);
+        ro_ids.push((store.clone(), ids.collect()));
+    }
+
+    let summaries = images
+        .into_iter()
+        .map(|img| {
+            let store = if !img.read_only {
+                ImageStore::Graphroot
+            } else {
+                ro_ids
+                    .iter()
+                    .find(|(_, ids)| ids.contains(&img.id))
+                    .map_or(ImageStore::UnknownReadOnly, |(store, _)| {
+                        ImageStore::ReadOnly(store.clone())
+                    })
+            };
+
+            ImageSummary {
+                id: img.id,
+                names: img.names.unwrap_or_default(),
+                digest: img.digest,
+                size: img.size,
+                created: img.created,
+                store,
+            }
+        })
+        .collect();
+
+    Ok(summaries)
+}
@@ -0,0 +1,112 @@
+// Labels identifying the containers created by the driver and who launched them, e.g. to
+// garbage-collect containers left behind by node failures or cancelled jobs
+use crate::{PodmanCtx, RmOptions, checked_output, commands};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+
+// Attached to every container created by the driver, which only ever cleans up those
+pub const MANAGED_LABEL: &str = "sarus-suite.managed";
+pub const LAUNCHER_VERSION_LABEL: &str = "sarus-suite.launcher-version";
+pub const USER_LABEL: &str = "sarus-suite.user";
+pub const SLURM_JOB_ID_LABEL: &str = "sarus-suite.slurm-job-id";
+pub const SLURM_STEP_ID_LABEL: &str = "sarus-suite.slurm-step-id";
+pub const EDF_LABEL: &str = "sarus-suite.edf";
+
+// Labels describing the current launch: launcher version, user, Slurm job and step, EDF.
+// Meant for `ContainerCtx::labels`, possibly extended with labels of the caller.
+pub fn launch_labels(launcher_version: &str, edf_path: Option<&Path>) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert(
+        LAUNCHER_VERSION_LABEL.to_string(),
+        launcher_version.to_string(),
+    );
+
+    let env_labels = [
+        (USER_LABEL, "USER"),
+        (SLURM_JOB_ID_LABEL, "SLURM_JOB_ID"),
+        (SLURM_STEP_ID_LABEL, "SLURM_STEP_ID"),
+    ];
+    for (label, var) in env_labels {
+        if let Ok(val) = env::var(var) {
+            labels.insert(label.to_string(), val);
+        }
+    }
+
+    if let Some(edf_path) = edf_path {
+        labels.insert(EDF_LABEL.to_string(), edf_path.display().to_string());
+    }
+    labels
+}
+
+// Stop and remove the driver-created containers carrying all of `labels`, e.g. those of a
+// Slurm job. An empty `labels` matches every container created by the driver.
+// Returns the IDs of the removed containers.
+pub fn cleanup_by_label(
+    labels: &BTreeMap<String, String>,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<Vec<String>> {
+    let output = checked_output(&mut commands::ps_by_label(labels, podman_ctx), "podman ps")?;
+    let ids: Vec<String> = str::from_utf8(&output.stdout)?
+        .lines()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+        .collect();
+
+    if !ids.is_empty() {
+        // Stop and remove, ignoring containers already gone, e.g. removed by `run --rm`
+        let rm = RmOptions {
+            force: true,
+            ignore: true,
+            ..Default::default()
+        };
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        checked_output(&mut commands::rm_with(&ids, &rm, podman_ctx), "podman rm")?;
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_cleanup_by_label() -> anyhow::Result<()> {
+        // Lists two containers for job 42 and none otherwise
+        let podman = FakePodman::new(
+            "labels",
+            r#"case "$*" in
+    ps*slurm-job-id=42*) printf 'aaaa1111\nbbbb2222\n' ;;
+esac
+"#,
+        );
+        let p_ctx = podman.ctx();
+
+        let job = BTreeMap::from([(SLURM_JOB_ID_LABEL.to_string(), String::from("42"))]);
+        assert_eq!(
+            cleanup_by_label(&job, Some(&p_ctx))?,
+            ["aaaa1111", "bbbb2222"]
+        );
+        let other_job = BTreeMap::from([(SLURM_JOB_ID_LABEL.to_string(), String::from("43"))]);
+        assert!(cleanup_by_label(&other_job, Some(&p_ctx))?.is_empty());
+
+        assert_eq!(
+            podman.calls(),
+            [
+                "ps --all --no-trunc --format {{.ID}} --filter label=sarus-suite.managed=true \
+                 --filter label=sarus-suite.slurm-job-id=42",
+                "rm --force --ignore aaaa1111 bbbb2222",
+                "ps --all --no-trunc --format {{.ID}} --filter label=sarus-suite.managed=true \
+                 --filter label=sarus-suite.slurm-job-id=43",
+            ]
+        );
+
+        let labels = launch_labels("1.2.3", Some(&PathBuf::from("/home/user/alpine.toml")));
+        assert_eq!(labels[LAUNCHER_VERSION_LABEL], "1.2.3");
+        assert_eq!(labels[EDF_LABEL], "/home/user/alpine.toml");
+        Ok(())
+    }
+}
@@ -1,24 +1,90 @@
-use anyhow::{self, Ok};
+use anyhow::Ok;
 use raster::EDF;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::prelude::*;
+use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Output};
 
+mod conf;
+mod duration;
+mod events;
+mod exit;
+mod guard;
+mod identity;
+mod images;
+mod labels;
+mod lifecycle;
+mod lock;
+mod logs;
+mod naming;
+mod parallax;
+mod process;
+mod profile;
+mod redirect;
+mod resources;
+mod state;
+mod stats;
+mod storage;
+#[cfg(test)]
+mod testutil;
+mod top;
+mod wait;
+pub use conf::{CONTAINERS_CONF_ENV, CONTAINERS_STORAGE_CONF_ENV, ConfFiles};
+pub use events::{Event, EventKind, EventStream, EventsOptions, events};
+pub use exit::{ContainerExit, ExitReason, run_from_edf_exit};
+pub use guard::{ContainerGuard, GuardOptions, run_from_edf_guarded};
+pub use identity::{Group, Identity, User, UserNsMode};
+pub use images::{ImageStore, ImageSummary, list_images};
+pub use labels::{
+    EDF_LABEL, LAUNCHER_VERSION_LABEL, MANAGED_LABEL, SLURM_JOB_ID_LABEL, SLURM_STEP_ID_LABEL,
+    USER_LABEL, cleanup_by_label, launch_labels,
+};
+pub use lifecycle::{
+    ContainerError, ContainerResult, RmOptions, StopOptions, kill, pause, restart, rm_all,
+    rm_containers, start, stop_all, stop_containers, unpause,
+};
+pub use lock::{ImageLock, LockConfig, lock_image};
+pub use logs::{
+    ContainerLogs, LogConfig, LogDriver, LogFollower, LogLine, LogSource, LogsOptions, follow_logs,
+    logs,
+};
+pub use naming::{NamePolicy, NamedContainer};
+pub use parallax::{
+    EnsureReport, EnsureStep, MigrationStatus, ParallaxCheck, PullPolicy, RoStoreImage,
+    ensure_image, parallax_check, parallax_check_in, parallax_list, parallax_list_store,
+    parallax_migrate, parallax_migrate_to, parallax_rmi, parallax_rmi_from, parallax_status,
+};
+pub use process::{CgroupStats, ContainerProcessInfo, container_process_info};
+pub use profile::{CgroupsMode, NamespaceMode, RuntimeProfile};
+pub use redirect::{
+    OutputFile, Redirect, run_from_edf_redirected, run_redirected, start_attached_redirected,
+};
+pub use resources::{ResourceLimits, Ulimit};
+pub use state::{ContainerStateReader, read_pidfile};
+pub use stats::{StatsSample, sample_stats, stats};
+pub use storage::StorageDriver;
+pub use top::{DEFAULT_TOP_DESCRIPTORS, TopRow, top};
+pub use wait::{WaitCondition, wait, wait_for_pid, wait_for_running};
+
 pub struct PodmanCtx {
     pub podman_path: PathBuf,
     pub module: Option<String>,
     pub graphroot: Option<PathBuf>,
     pub runroot: Option<PathBuf>,
     pub parallax_mount_program: Option<PathBuf>,
-    pub ro_store: Option<PathBuf>,
+    // Read-only additional image stores, in lookup order.
+    // The first one is where parallax migrates images by default.
+    pub ro_stores: Vec<PathBuf>,
+    // Serializes parallax runs on the same image of a read-only store
+    pub parallax_lock: LockConfig,
 
     pub podman_env: Option<HashMap<OsString, OsString>>,
 }
 
-//// tiny helper to simplify set podman execution env as:
+// tiny helper to simplify set podman execution env as:
 // let p_ctx = PodmanCtx {
 //    // ...normal fields...
 //    podman_env: None,
@@ -32,14 +98,34 @@ impl PodmanCtx {
             .insert(k.into(), v.into());
         self
     }
+
+    pub fn ro_store(&self) -> Option<&Path> {
+        self.ro_stores.first().map(PathBuf::as_path)
+    }
 }
 
+#[derive(Clone, Default)]
 pub struct ContainerCtx {
     pub name: String,
+    pub name_policy: NamePolicy,
     pub interactive: bool,
     pub detach: bool,
     pub set_env: bool,
     pub pidfile: Option<PathBuf>,
+    pub resources: ResourceLimits,
+    pub profile: RuntimeProfile,
+    pub identity: Identity,
+    // Added to the label marking the container as created by the driver, see launch_labels()
+    pub labels: BTreeMap<String, String>,
+    pub log: LogConfig,
+}
+
+impl ContainerCtx {
+    // Reject settings podman would ignore or that contradict each other. Launching checks it.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.identity.check()?;
+        self.profile.check(&self.resources)
+    }
 }
 
 mod commands {
@@ -69,26 +155,23 @@ mod commands {
             ctx.runroot.as_deref().map(Path::as_os_str),
         );
 
+        // Every subcommand gets the same module and storage options, otherwise e.g. an image
+        // pulled or inspected could resolve against a different store layout than `run` uses
+        cli_opt(&mut cmd, "--module", ctx.module.as_deref().map(OsStr::new));
+        for store in &ctx.ro_stores {
+            cli_storage_opt(&mut cmd, "additionalimagestore", Some(store.as_os_str()));
+        }
+        cli_storage_opt(
+            &mut cmd,
+            "mount_program",
+            ctx.parallax_mount_program.as_deref().map(Path::as_os_str),
+        );
+
         cmd
     }
 
     pub fn run(podman_ctx: Option<&PodmanCtx>) -> Command {
         let mut cmd = base(podman_ctx);
-
-        if let Some(ctx) = podman_ctx {
-            cli_opt(&mut cmd, "--module", ctx.module.as_deref().map(OsStr::new));
-            cli_storage_opt(
-                &mut cmd,
-                "additionalimagestore",
-                ctx.ro_store.as_deref().map(Path::as_os_str),
-            );
-            cli_storage_opt(
-                &mut cmd,
-                "mount_program",
-                ctx.parallax_mount_program.as_deref().map(Path::as_os_str),
-            );
-        }
-
         cmd.arg("run");
         cmd
     }
@@ -99,6 +182,22 @@ mod commands {
         c_ctx: &ContainerCtx,
         container_cmd: I,
     ) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        run_from_edf_stdin(edf, p_ctx, c_ctx, container_cmd, false)
+    }
+
+    // `stdin` forwards stdin to the container without allocating a terminal, unlike
+    // `ContainerCtx::interactive`, e.g. to feed it from a file
+    pub fn run_from_edf_stdin<I, S>(
+        edf: &EDF,
+        p_ctx: Option<&PodmanCtx>,
+        c_ctx: &ContainerCtx,
+        container_cmd: I,
+        stdin: bool,
+    ) -> Command
     where
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>,
@@ -107,45 +206,246 @@ mod commands {
 
         cmd.arg("--rm");
         cli_flag(&mut cmd, c_ctx.detach, "--detach");
-        cli_flag(&mut cmd, c_ctx.interactive, "-it");
-        cli_flag(&mut cmd, !edf.writable, "--read-only");
+        cli_flag(&mut cmd, stdin, "--interactive");
+        edf_args(&mut cmd, edf, c_ctx, container_cmd);
+
+        cmd
+    }
+
+    // Same container as run_from_edf(), created but not started. `ContainerCtx::detach` does
+    // not apply: that is up to start.
+    pub fn create_from_edf<I, S>(
+        edf: &EDF,
+        p_ctx: Option<&PodmanCtx>,
+        c_ctx: &ContainerCtx,
+        container_cmd: I,
+    ) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        create_from_edf_stdin(edf, p_ctx, c_ctx, container_cmd, false)
+    }
+
+    // `stdin` keeps stdin open without a terminal, as run_from_edf_stdin() does
+    pub fn create_from_edf_stdin<I, S>(
+        edf: &EDF,
+        p_ctx: Option<&PodmanCtx>,
+        c_ctx: &ContainerCtx,
+        container_cmd: I,
+        stdin: bool,
+    ) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut cmd = base(p_ctx);
+
+        cmd.args(["create", "--rm"]);
+        cli_flag(&mut cmd, stdin, "--interactive");
+        edf_args(&mut cmd, edf, c_ctx, container_cmd);
+
+        cmd
+    }
+
+    // Same as create_from_edf() without --rm, the container is kept for inspection after it exits
+    pub fn create_from_edf_kept<I, S>(
+        edf: &EDF,
+        p_ctx: Option<&PodmanCtx>,
+        c_ctx: &ContainerCtx,
+        container_cmd: I,
+    ) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut cmd = base(p_ctx);
+
+        cmd.arg("create");
+        edf_args(&mut cmd, edf, c_ctx, container_cmd);
 
-        cli_opt(&mut cmd, "--name", Some(OsStr::new(&c_ctx.name)));
+        cmd
+    }
+
+    // Everything defining the container, shared by run and create
+    fn edf_args<I, S>(cmd: &mut Command, edf: &EDF, c_ctx: &ContainerCtx, container_cmd: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        cli_flag(cmd, c_ctx.interactive, "-it");
+        cli_flag(cmd, !edf.writable, "--read-only");
+
+        cli_opt(cmd, "--name", Some(OsStr::new(&c_ctx.name)));
+        cli_flag(cmd, c_ctx.name_policy == NamePolicy::Replace, "--replace");
         cli_opt(
-            &mut cmd,
+            cmd,
             "--pidfile",
             c_ctx.pidfile.as_deref().map(Path::as_os_str),
         );
 
         //TODO: support entrypoint redefinition as well
-        cli_flag(&mut cmd, !edf.entrypoint, "--entrypoint=");
+        cli_flag(cmd, !edf.entrypoint, "--entrypoint=");
+
+        resource_args(cmd, &c_ctx.resources);
+        profile_args(cmd, &c_ctx.profile);
+        identity_args(cmd, &c_ctx.identity);
+        label_args(cmd, &c_ctx.labels);
+        log_args(cmd, &c_ctx.log);
 
         if !edf.workdir.is_empty() {
-            cli_opt(&mut cmd, "--workdir", Some(OsStr::new(&edf.workdir)));
+            cli_opt(cmd, "--workdir", Some(OsStr::new(&edf.workdir)));
         }
         for mnt in &edf.mounts {
-            cli_opt(
-                &mut cmd,
-                "--volume",
-                Some(OsStr::new(&mnt.to_volume_string())),
-            );
+            cli_opt(cmd, "--volume", Some(OsStr::new(&mnt.to_volume_string())));
         }
         for dev in &edf.devices {
-            cli_opt(&mut cmd, "--device", Some(OsStr::new(dev)));
+            cli_opt(cmd, "--device", Some(OsStr::new(dev)));
         }
         for (key, val) in &edf.env {
-            cli_kv(&mut cmd, "--env", OsStr::new(key), OsStr::new(val));
+            cli_kv(cmd, "--env", OsStr::new(key), OsStr::new(val));
         }
         for (key, val) in &edf.annotations {
-            cli_kv(&mut cmd, "--annotation", OsStr::new(key), OsStr::new(val));
+            cli_kv(cmd, "--annotation", OsStr::new(key), OsStr::new(val));
         }
 
         cmd.arg(&edf.image);
         cmd.args(container_cmd);
+    }
+
+    pub fn log_args(cmd: &mut Command, log: &LogConfig) {
+        cli_opt(
+            cmd,
+            "--log-driver",
+            log.driver.map(LogDriver::as_str).map(OsStr::new),
+        );
+        if let Some(path) = &log.path {
+            cli_kv(cmd, "--log-opt", OsStr::new("path"), path.as_os_str());
+        }
+        if let Some(max_size) = log.max_size {
+            let max_size = max_size.to_string();
+            cli_kv(
+                cmd,
+                "--log-opt",
+                OsStr::new("max-size"),
+                OsStr::new(&max_size),
+            );
+        }
+    }
+
+    pub fn logs(
+        name: &str,
+        options: &LogsOptions,
+        follow: bool,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.arg("logs");
+        cli_flag(&mut cmd, follow, "--follow");
+        cli_opt(
+            &mut cmd,
+            "--since",
+            options.since.as_deref().map(OsStr::new),
+        );
+        cli_opt(
+            &mut cmd,
+            "--until",
+            options.until.as_deref().map(OsStr::new),
+        );
+        let tail = options.tail.map(|lines| lines.to_string());
+        cli_opt(&mut cmd, "--tail", tail.as_deref().map(OsStr::new));
+        cli_flag(&mut cmd, options.timestamps, "--timestamps");
+        cmd.arg(name);
+        cmd
+    }
 
+    // Attaching forwards the output of the container and its exit code, like an attached run
+    pub fn start(
+        name: &str,
+        attach: bool,
+        interactive: bool,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.arg("start");
+        cli_flag(&mut cmd, attach, "--attach");
+        cli_flag(&mut cmd, interactive, "--interactive");
+        cmd.arg(name);
         cmd
     }
 
+    pub fn resource_args(cmd: &mut Command, limits: &ResourceLimits) {
+        let numeric = [
+            ("--cpus", limits.cpus.map(|cpus| cpus.to_string())),
+            ("--memory", limits.memory.map(|bytes| bytes.to_string())),
+            (
+                "--pids-limit",
+                limits.pids_limit.map(|pids| pids.to_string()),
+            ),
+            ("--shm-size", limits.shm_size.map(|bytes| bytes.to_string())),
+        ];
+        for (name, val) in &numeric {
+            cli_opt(cmd, name, val.as_deref().map(OsStr::new));
+        }
+        cli_opt(
+            cmd,
+            "--cpuset-cpus",
+            limits.cpuset_cpus.as_deref().map(OsStr::new),
+        );
+        for ulimit in &limits.ulimits {
+            cli_opt(cmd, "--ulimit", Some(OsStr::new(&ulimit.to_arg())));
+        }
+    }
+
+    pub fn profile_args(cmd: &mut Command, profile: &RuntimeProfile) {
+        let namespaces = [
+            ("--network", &profile.network),
+            ("--ipc", &profile.ipc),
+            ("--pid", &profile.pid),
+            ("--cgroupns", &profile.cgroupns),
+        ];
+        for (name, mode) in namespaces {
+            let mode = mode.as_ref().map(NamespaceMode::to_arg);
+            cli_opt(cmd, name, mode.as_deref().map(OsStr::new));
+        }
+
+        cli_opt(
+            cmd,
+            "--cgroups",
+            profile.cgroups.map(CgroupsMode::as_str).map(OsStr::new),
+        );
+        if profile.host_ulimits {
+            cmd.args(["--ulimit", "host"]);
+        }
+    }
+
+    pub fn identity_args(cmd: &mut Command, identity: &Identity) {
+        let user = identity.user.as_ref().map(User::to_arg);
+        cli_opt(cmd, "--user", user.as_deref().map(OsStr::new));
+        for group in &identity.group_add {
+            cli_opt(cmd, "--group-add", Some(OsStr::new(&group.to_arg())));
+        }
+        cli_opt(
+            cmd,
+            "--passwd-entry",
+            identity.passwd_entry.as_deref().map(OsStr::new),
+        );
+        let userns = identity.userns.as_ref().map(UserNsMode::to_arg);
+        cli_opt(cmd, "--userns", userns.as_deref().map(OsStr::new));
+    }
+
+    pub fn label_args(cmd: &mut Command, labels: &BTreeMap<String, String>) {
+        cli_kv(
+            cmd,
+            "--label",
+            OsStr::new(MANAGED_LABEL),
+            OsStr::new("true"),
+        );
+        for (key, val) in labels {
+            cli_kv(cmd, "--label", OsStr::new(key), OsStr::new(val));
+        }
+    }
+
     pub fn pull(image: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
         let mut cmd = base(podman_ctx);
         cmd.args(["pull", image]);
@@ -154,90 +454,178 @@ mod commands {
 
     pub fn rmi(image: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
         let mut cmd = base(podman_ctx);
-
-        if let Some(ctx) = podman_ctx {
-            cli_storage_opt(
-                &mut cmd,
-                "additionalimagestore",
-                ctx.ro_store.as_deref().map(Path::as_os_str),
-            );
-        }
-
         cmd.args(["rmi", image]);
         cmd
     }
 
     pub fn rm(name: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
         let mut cmd = base(podman_ctx);
+        cmd.args(["rm", name]);
+        cmd
+    }
 
-        if let Some(ctx) = podman_ctx {
-            cli_storage_opt(
-                &mut cmd,
-                "additionalimagestore",
-                ctx.ro_store.as_deref().map(Path::as_os_str),
-            );
-        }
+    // Full IDs of all the driver-created containers carrying `labels`
+    pub fn ps_by_label(
+        labels: &BTreeMap<String, String>,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut filters = vec![format!("label={MANAGED_LABEL}=true")];
+        filters.extend(labels.iter().map(|(key, val)| format!("label={key}={val}")));
+        let filters: Vec<&str> = filters.iter().map(String::as_str).collect();
+        ps(&filters, true, "{{.ID}}", podman_ctx)
+    }
 
-        cmd.args(["rm", name]);
+    pub fn stop_with(
+        containers: &[&str],
+        options: &StopOptions,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.arg("stop");
+        cli_flag(&mut cmd, options.ignore, "--ignore");
+        let timeout = options.timeout.map(|secs| secs.to_string());
+        cli_opt(&mut cmd, "--time", timeout.as_deref().map(OsStr::new));
+        cmd.args(containers);
         cmd
     }
 
-    pub fn stop(name: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
-        let mut cmd = commands::base(podman_ctx);
+    pub fn rm_with(
+        containers: &[&str],
+        options: &RmOptions,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.arg("rm");
+        cli_flag(&mut cmd, options.force, "--force");
+        cli_flag(&mut cmd, options.ignore, "--ignore");
+        cli_flag(&mut cmd, options.volumes, "--volumes");
+        let timeout = options.timeout.map(|secs| secs.to_string());
+        cli_opt(&mut cmd, "--time", timeout.as_deref().map(OsStr::new));
+        cmd.args(containers);
+        cmd
+    }
 
-        if let Some(ctx) = podman_ctx {
-            cli_storage_opt(
-                &mut cmd,
-                "additionalimagestore",
-                ctx.ro_store.as_deref().map(Path::as_os_str),
-            );
+    pub fn kill(
+        containers: &[&str],
+        signal: Option<&str>,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.arg("kill");
+        cli_opt(&mut cmd, "--signal", signal.map(OsStr::new));
+        cmd.args(containers);
+        cmd
+    }
+
+    pub fn top(name: &str, descriptors: &[&str], podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.args(["top", name]);
+        cmd.args(descriptors);
+        cmd
+    }
+
+    pub fn stats(name: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.args(["stats", "--no-stream", "--format", "json", name]);
+        cmd
+    }
+
+    pub fn pause(name: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.args(["pause", name]);
+        cmd
+    }
+
+    pub fn unpause(name: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.args(["unpause", name]);
+        cmd
+    }
+
+    pub fn restart(name: &str, timeout: Option<u32>, podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.arg("restart");
+        let timeout = timeout.map(|secs| secs.to_string());
+        cli_opt(&mut cmd, "--time", timeout.as_deref().map(OsStr::new));
+        cmd.arg(name);
+        cmd
+    }
+
+    // One JSON object per line, until `EventsOptions::until` or until killed
+    pub fn events(options: &EventsOptions, podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.args(["events", "--format", "json"]);
+        for container in &options.containers {
+            cmd.args(["--filter", &format!("container={container}")]);
+        }
+        for (key, val) in &options.labels {
+            cmd.args(["--filter", &format!("label={key}={val}")]);
+        }
+        for event in &options.events {
+            cmd.args(["--filter", &format!("event={}", event.as_str())]);
+        }
+        cli_opt(
+            &mut cmd,
+            "--since",
+            options.since.as_deref().map(OsStr::new),
+        );
+        cli_opt(
+            &mut cmd,
+            "--until",
+            options.until.as_deref().map(OsStr::new),
+        );
+        cmd
+    }
+
+    // Containers matching all of `filters`, only running ones unless `all`
+    pub fn ps(
+        filters: &[&str],
+        all: bool,
+        format: &str,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.arg("ps");
+        cli_flag(&mut cmd, all, "--all");
+        cmd.args(["--no-trunc", "--format", format]);
+        for filter in filters {
+            cmd.args(["--filter", filter]);
         }
+        cmd
+    }
 
+    pub fn stop(name: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = commands::base(podman_ctx);
         cmd.args(["stop", name]);
         cmd
     }
 
     pub fn image_exists(image: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
         let mut cmd = commands::base(podman_ctx);
-
-        if let Some(ctx) = podman_ctx {
-            cli_storage_opt(
-                &mut cmd,
-                "additionalimagestore",
-                ctx.ro_store.as_deref().map(Path::as_os_str),
-            );
-        }
-
         cmd.args(["image", "exists", image]);
         cmd
     }
 
+    pub fn container_exists(name: &str, podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = base(podman_ctx);
+        cmd.args(["container", "exists", name]);
+        cmd
+    }
+
     pub fn images(podman_ctx: Option<&PodmanCtx>) -> Command {
         let mut cmd = commands::base(podman_ctx);
-
-        if let Some(ctx) = podman_ctx {
-            cli_storage_opt(
-                &mut cmd,
-                "additionalimagestore",
-                ctx.ro_store.as_deref().map(Path::as_os_str),
-            );
-        }
-
         cmd.arg("images");
         cmd
     }
 
+    pub fn images_json(podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = commands::images(podman_ctx);
+        cmd.args(["--format", "json"]);
+        cmd
+    }
+
     pub fn inspect(target: &str, format: Option<&str>, podman_ctx: Option<&PodmanCtx>) -> Command {
         let mut cmd = commands::base(podman_ctx);
-
-        if let Some(ctx) = podman_ctx {
-            cli_storage_opt(
-                &mut cmd,
-                "additionalimagestore",
-                ctx.ro_store.as_deref().map(Path::as_os_str),
-            );
-        }
-
         cmd.args(["--log-level=error", "inspect"]);
 
         if let Some(fmt) = format {
@@ -259,10 +647,24 @@ mod commands {
         cmd
     }
 
-    pub fn version(module: Option<&str>) -> Command {
-        let mut cmd = commands::base(None);
-        cli_opt(&mut cmd, "--module", module.map(OsStr::new));
+    pub fn wait(
+        name: &str,
+        conditions: &[WaitCondition],
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> Command {
+        let mut cmd = commands::base(podman_ctx);
+        cmd.arg("wait");
+
+        for cond in conditions {
+            cli_opt(&mut cmd, "--condition", Some(OsStr::new(cond.as_str())));
+        }
+
+        cmd.arg(name);
+        cmd
+    }
 
+    pub fn version(podman_ctx: Option<&PodmanCtx>) -> Command {
+        let mut cmd = commands::base(podman_ctx);
         cmd.arg("version");
         cmd
     }
@@ -270,28 +672,19 @@ mod commands {
     pub fn parallax(
         parallax_path: &PathBuf,
         podman_ctx: &PodmanCtx,
+        ro_store: &Path,
         image: &str,
         action: &str,
-    ) -> Command {
+    ) -> anyhow::Result<Command> {
         let mut cmd = Command::new(parallax_path);
 
         cmd.arg("--podmanRoot")
-            .arg(
-                podman_ctx
-                    .graphroot
-                    .as_ref()
-                    .expect("Missing graphroot in parallax_migrate()"),
-            )
+            .arg(crate::parallax::graphroot(podman_ctx)?)
             .arg("--roStoragePath")
-            .arg(
-                &podman_ctx
-                    .ro_store
-                    .as_ref()
-                    .expect("Missing read-only store path in parallax_migrate()"),
-            );
+            .arg(ro_store);
 
         cmd.arg(format!("--{action}")).arg("--image").arg(image);
-        cmd
+        Ok(cmd)
     }
 }
 
@@ -317,32 +710,107 @@ where
         .expect("Failed to execute command")
 }
 
+// Returns the exit status of podman and the name the container was run under, which differs
+// from `ContainerCtx::name` with `NamePolicy::UniqueSuffix`
 pub fn run_from_edf<I, S>(
     edf: &EDF,
     p_ctx: Option<&PodmanCtx>,
     c_ctx: &ContainerCtx,
     container_cmd: I,
-) -> ExitStatus
+) -> (ExitStatus, String)
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    commands::run_from_edf(edf, p_ctx, c_ctx, container_cmd)
+    let report = |e: anyhow::Error| {
+        let out = rejected(&e);
+        let _ = std::io::stderr().write_all(&out.stderr);
+        (out.status, c_ctx.name.clone())
+    };
+    if let Err(e) = c_ctx.validate() {
+        return report(e);
+    }
+    if c_ctx.name_policy == NamePolicy::UniqueSuffix {
+        return naming::run_named(edf, p_ctx, c_ctx, container_cmd).unwrap_or_else(report);
+    }
+    let status = commands::run_from_edf(edf, p_ctx, c_ctx, container_cmd)
         .status()
-        .expect("Failed to execute command")
+        .expect("Failed to execute command");
+    (status, c_ctx.name.clone())
 }
 
+// Same as run_from_edf(), with the output captured
 pub fn run_from_edf_output<I, S>(
     edf: &EDF,
     p_ctx: Option<&PodmanCtx>,
     c_ctx: &ContainerCtx,
     container_cmd: I,
-) -> Output
+) -> (Output, String)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    if let Err(e) = c_ctx.validate() {
+        return (rejected(&e), c_ctx.name.clone());
+    }
+    if c_ctx.name_policy == NamePolicy::UniqueSuffix {
+        let created = match naming::create_named(edf, p_ctx, c_ctx, container_cmd, false, false) {
+            std::result::Result::Ok(created) => created,
+            Err(e) => return (rejected(&e), c_ctx.name.clone()),
+        };
+        let output = naming::start_named(&created.id, p_ctx, c_ctx)
+            .output()
+            .expect("Failed to execute command");
+        return (output, created.name);
+    }
+    let output = commands::run_from_edf(edf, p_ctx, c_ctx, container_cmd)
+        .output()
+        .expect("Failed to execute command");
+    (output, c_ctx.name.clone())
+}
+
+// Create the container ahead of time, e.g. during the job prolog, and return its ID and the
+// name it was given, see `NamePolicy::UniqueSuffix`
+pub fn create_from_edf<I, S>(
+    edf: &EDF,
+    p_ctx: Option<&PodmanCtx>,
+    c_ctx: &ContainerCtx,
+    container_cmd: I,
+) -> anyhow::Result<NamedContainer>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    commands::run_from_edf(edf, p_ctx, c_ctx, container_cmd)
+    c_ctx.validate()?;
+    naming::create_named(edf, p_ctx, c_ctx, container_cmd, false, false)
+}
+
+// Same as create_from_edf(), with stdin kept open but no terminal, e.g. for
+// start_attached_redirected() to feed it from a file
+pub fn create_from_edf_stdin<I, S>(
+    edf: &EDF,
+    p_ctx: Option<&PodmanCtx>,
+    c_ctx: &ContainerCtx,
+    container_cmd: I,
+) -> anyhow::Result<NamedContainer>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    c_ctx.validate()?;
+    naming::create_named(edf, p_ctx, c_ctx, container_cmd, true, false)
+}
+
+// Start a created container and wait for it, with its output forwarded as for run_from_edf().
+// `interactive` also forwards stdin, for containers created with `ContainerCtx::interactive`.
+pub fn start_attached(name: &str, interactive: bool, podman_ctx: Option<&PodmanCtx>) -> ExitStatus {
+    commands::start(name, true, interactive, podman_ctx)
+        .status()
+        .expect("Failed to execute command")
+}
+
+pub fn start_attached_output(name: &str, podman_ctx: Option<&PodmanCtx>) -> Output {
+    commands::start(name, true, false, podman_ctx)
         .output()
         .expect("Failed to execute command")
 }
@@ -377,11 +845,18 @@ pub fn images(podman_ctx: Option<&PodmanCtx>) {
         .expect("Failed to execute command");
 }
 
+// Reads the storage metadata directly first, which is much faster than spawning
+// `podman image exists`. Images not found there are left to podman.
 pub fn image_exists(image: &str, podman_ctx: Option<&PodmanCtx>) -> bool {
+    if podman_ctx.is_some_and(|ctx| storage::image_found(image, ctx)) {
+        return true;
+    }
+
     commands::image_exists(image, podman_ctx)
         .output()
         .expect("Failed to execute command")
-        .status.success()
+        .status
+        .success()
 }
 
 pub fn inspect(target: &str, format: Option<&str>, podman_ctx: Option<&PodmanCtx>) -> Output {
@@ -396,8 +871,8 @@ pub fn info(format: Option<&str>, podman_ctx: Option<&PodmanCtx>) -> Output {
         .expect("Failed to execute command")
 }
 
-pub fn version(module: Option<&str>) -> Output {
-    commands::version(module)
+pub fn version(podman_ctx: Option<&PodmanCtx>) -> Output {
+    commands::version(podman_ctx)
         .output()
         .expect("Failed to execute command")
 }
@@ -427,6 +902,7 @@ pub fn get_container_pid(name: &str, podman_ctx: Option<&PodmanCtx>) -> anyhow::
 //   - the container is stopped
 //   - a custom pidfile was specified in `podman run`
 //   - storage driver is not overlay
+// See get_container_pid_from_storage() for a version lifting these restrictions but the first.
 pub fn get_container_pid_from_default_file(
     container_id: &str,
     runroot: Option<&PathBuf>,
@@ -453,42 +929,53 @@ pub fn get_container_pid_from_default_file(
 
     let mut pid = String::new();
     cnt_pidfile.read_to_string(&mut pid)?;
-    let pid: u32 = pid.parse()?;
+    let pid: u32 = pid.trim().parse()?;
     Ok(pid)
 }
 
-fn parallax_execute_command(
-    parallax_path: &PathBuf,
-    podman_ctx: &PodmanCtx,
-    image: &str,
-    action: &str,
-) -> anyhow::Result<()> {
-    let output = commands::parallax(parallax_path, podman_ctx, image, action)
-        .output()
-        .expect(&format!("Failed to execute `parallax {action}`"));
+// Retrieves the pid of a running container from its pidfile, without spawning podman if the
+// storage locations are known (see ContainerStateReader::new()).
+// Accepts a container name or ID, supports the overlay and vfs drivers and honors a custom
+// pidfile, i.e. the one given as ContainerCtx::pidfile.
+pub fn get_container_pid_from_storage(
+    name_or_id: &str,
+    pidfile: Option<&Path>,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<u32> {
+    ContainerStateReader::new(podman_ctx)?.container_pid(name_or_id, pidfile)
+}
 
-    if !output.status.success() {
-        // include stderr to make debugging nicer
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("parallax {action} failed: {}", stderr.trim());
+// Output of a command podman would have rejected: the error on stderr and exit code 125
+pub(crate) fn rejected(err: &anyhow::Error) -> Output {
+    Output {
+        status: ExitStatus::from_raw(125 << 8),
+        stdout: Vec::new(),
+        stderr: format!("Error: {err:#}\n").into_bytes(),
     }
-    Ok(())
 }
 
-pub fn parallax_migrate(
-    parallax_path: &PathBuf,
-    podman_ctx: &PodmanCtx,
-    image: &str,
-) -> anyhow::Result<()> {
-    parallax_execute_command(parallax_path, podman_ctx, image, "migrate")
+// Run a command and turn a non-zero exit into an error carrying its stderr
+pub(crate) fn checked_output(cmd: &mut Command, what: &str) -> anyhow::Result<Output> {
+    checked(cmd.output().expect("Failed to execute command"), what)
 }
 
-pub fn parallax_rmi(
-    parallax_path: &PathBuf,
-    podman_ctx: &PodmanCtx,
-    image: &str,
-) -> anyhow::Result<()> {
-    parallax_execute_command(parallax_path, podman_ctx, image, "rmi")
+pub(crate) fn checked(output: Output, what: &str) -> anyhow::Result<Output> {
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{what} failed: {}", stderr.trim());
+    }
+    Ok(output)
+}
+
+// For commands built more than once from the same arguments, e.g. retried launches
+pub(crate) fn owned_args<I, S>(args: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    args.into_iter()
+        .map(|arg| arg.as_ref().to_os_string())
+        .collect()
 }
 
 fn cli_flag(cmd: &mut Command, on: bool, name: &str) {
@@ -535,18 +1022,14 @@ pub mod loggable {
             Some(s) => s.to_string(),
             None => String::from(""),
         };
-        outstr.push_str(" ");
+        outstr.push(' ');
 
         for arg in cmd.get_args() {
-            outstr.push_str(" ");
-            let strarg = match arg.to_str() {
-                Some(s) => s,
-                None => "<CANNOT CONVERT>",
-            };
-            outstr.push_str(strarg);
+            outstr.push(' ');
+            outstr.push_str(arg.to_str().unwrap_or("<CANNOT CONVERT>"));
         }
 
-        return outstr;
+        outstr
     }
 
     #[derive(Clone)] //TODO: do we need this to be clonable?
@@ -555,17 +1038,92 @@ pub mod loggable {
         pub output: Output,
     }
 
+    impl ExecutedCommand {
+        // Outcome of a command operating on `container`, with the error classified
+        pub fn container_result(&self, container: &str) -> Result<(), ContainerError> {
+            if self.output.status.success() {
+                return std::result::Result::Ok(());
+            }
+            let stderr = String::from_utf8_lossy(&self.output.stderr);
+            Err(ContainerError::from_podman(container, &stderr))
+        }
+    }
+
+    // Also returns the name the container was given, as the unlogged run_from_edf()
     pub fn run_from_edf<I, S>(
         edf: &EDF,
         p_ctx: Option<&PodmanCtx>,
         c_ctx: &ContainerCtx,
         container_cmd: I,
-    ) -> ExecutedCommand
+    ) -> (ExecutedCommand, String)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let container_cmd = owned_args(container_cmd);
+        if c_ctx.name_policy != NamePolicy::UniqueSuffix {
+            return launch_logged(c_ctx, |c_ctx| {
+                commands::run_from_edf(edf, p_ctx, c_ctx, &container_cmd)
+            });
+        }
+
+        // The name is settled by create before the container runs, both logged as one command
+        let (created, name) = launch_logged(c_ctx, |c_ctx| {
+            commands::create_from_edf(edf, p_ctx, c_ctx, &container_cmd)
+        });
+        if !created.output.status.success() {
+            return (created, name);
+        }
+        let id = String::from_utf8_lossy(&created.output.stdout);
+        let mut cmd = naming::start_named(id.trim(), p_ctx, c_ctx);
+        let executed = ExecutedCommand {
+            command: format!("{} && {}", created.command, cmd2string(&cmd)),
+            output: cmd.output().expect("Failed to execute command"),
+        };
+        (executed, name)
+    }
+
+    pub fn create_from_edf<I, S>(
+        edf: &EDF,
+        p_ctx: Option<&PodmanCtx>,
+        c_ctx: &ContainerCtx,
+        container_cmd: I,
+    ) -> (ExecutedCommand, String)
     where
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>,
     {
-        let mut cmd = commands::run_from_edf(edf, p_ctx, c_ctx, container_cmd);
+        let container_cmd = owned_args(container_cmd);
+        launch_logged(c_ctx, |c_ctx| {
+            commands::create_from_edf(edf, p_ctx, c_ctx, &container_cmd)
+        })
+    }
+
+    // The last attempt is the one logged, and returned with the name the container was finally
+    // given
+    fn launch_logged(
+        c_ctx: &ContainerCtx,
+        mut build: impl FnMut(&ContainerCtx) -> Command,
+    ) -> (ExecutedCommand, String) {
+        let mut command = cmd2string(&build(c_ctx));
+        if let Err(e) = c_ctx.validate() {
+            let executed = ExecutedCommand {
+                command,
+                output: rejected(&e),
+            };
+            return (executed, c_ctx.name.clone());
+        }
+        let (output, name) = naming::launch_named(c_ctx, |c_ctx| {
+            let mut cmd = build(c_ctx);
+            command = cmd2string(&cmd);
+            cmd.output()
+        })
+        .expect("Failed to execute command");
+        (ExecutedCommand { command, output }, name)
+    }
+
+    pub fn start(name: &str, attach: bool, podman_ctx: Option<&PodmanCtx>) -> ExecutedCommand {
+        let mut cmd = commands::start(name, attach, false, podman_ctx);
 
         ExecutedCommand {
             command: cmd2string(&cmd),
@@ -600,6 +1158,50 @@ pub mod loggable {
         }
     }
 
+    pub fn kill(
+        name: &str,
+        signal: Option<&str>,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> ExecutedCommand {
+        let mut cmd = commands::kill(&[name], signal, podman_ctx);
+
+        ExecutedCommand {
+            command: cmd2string(&cmd),
+            output: cmd.output().expect("Failed to execute command"),
+        }
+    }
+
+    pub fn pause(name: &str, podman_ctx: Option<&PodmanCtx>) -> ExecutedCommand {
+        let mut cmd = commands::pause(name, podman_ctx);
+
+        ExecutedCommand {
+            command: cmd2string(&cmd),
+            output: cmd.output().expect("Failed to execute command"),
+        }
+    }
+
+    pub fn unpause(name: &str, podman_ctx: Option<&PodmanCtx>) -> ExecutedCommand {
+        let mut cmd = commands::unpause(name, podman_ctx);
+
+        ExecutedCommand {
+            command: cmd2string(&cmd),
+            output: cmd.output().expect("Failed to execute command"),
+        }
+    }
+
+    pub fn restart(
+        name: &str,
+        timeout: Option<u32>,
+        podman_ctx: Option<&PodmanCtx>,
+    ) -> ExecutedCommand {
+        let mut cmd = commands::restart(name, timeout, podman_ctx);
+
+        ExecutedCommand {
+            command: cmd2string(&cmd),
+            output: cmd.output().expect("Failed to execute command"),
+        }
+    }
+
     pub fn image_exists(image: &str, podman_ctx: Option<&PodmanCtx>) -> ExecutedCommand {
         let mut cmd = commands::image_exists(image, podman_ctx);
 
@@ -612,18 +1214,33 @@ pub mod loggable {
     fn parallax_execute_command(
         parallax_path: &PathBuf,
         podman_ctx: &PodmanCtx,
+        ro_store: &Path,
         image: &str,
         action: &str,
     ) -> ExecutedCommand {
-        let mut cmd = commands::parallax(parallax_path, podman_ctx, image, action);
+        // Failing to build the command (no graphroot), to lock (e.g. timing out, or no lock
+        // directory in the store) or to run parallax is reported as a failed command
+        let failed = |e: anyhow::Error| Output {
+            status: ExitStatus::from_raw(1 << 8),
+            stdout: Vec::new(),
+            stderr: format!("Error: parallax {action}: {e:#}\n").into_bytes(),
+        };
+        let mut cmd = match commands::parallax(parallax_path, podman_ctx, ro_store, image, action) {
+            std::result::Result::Ok(cmd) => cmd,
+            Err(e) => {
+                return ExecutedCommand {
+                    command: parallax_path.display().to_string(),
+                    output: failed(e),
+                };
+            }
+        };
+        let command = cmd2string(&cmd);
 
-        ExecutedCommand {
-            command: cmd2string(&cmd),
-            output: cmd
-                .output()
-                .expect(&format!("Failed to `parallax {action}`")),
-        }
+        let output = lock::lock_image(ro_store, image, &podman_ctx.parallax_lock)
+            .and_then(|_lock| Ok(cmd.output()?))
+            .unwrap_or_else(failed);
 
+        ExecutedCommand { command, output }
     }
 
     pub fn parallax_migrate(
@@ -631,14 +1248,58 @@ pub mod loggable {
         podman_ctx: &PodmanCtx,
         image: &str,
     ) -> ExecutedCommand {
-        parallax_execute_command(parallax_path, podman_ctx, image, "migrate")
+        let ro_store = podman_ctx
+            .ro_store()
+            .expect("Missing read-only store path in parallax_migrate()");
+        parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "migrate")
+    }
+
+    pub fn parallax_migrate_to(
+        parallax_path: &PathBuf,
+        podman_ctx: &PodmanCtx,
+        ro_store: &Path,
+        image: &str,
+    ) -> ExecutedCommand {
+        parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "migrate")
+    }
+
+    pub fn parallax_rmi(
+        parallax_path: &PathBuf,
+        podman_ctx: &PodmanCtx,
+        image: &str,
+    ) -> ExecutedCommand {
+        let ro_store = podman_ctx
+            .ro_store()
+            .expect("Missing read-only store path in parallax_rmi()");
+        parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "rmi")
+    }
+
+    pub fn parallax_rmi_from(
+        parallax_path: &PathBuf,
+        podman_ctx: &PodmanCtx,
+        ro_store: &Path,
+        image: &str,
+    ) -> ExecutedCommand {
+        parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "rmi")
+    }
+
+    pub fn parallax_check(
+        parallax_path: &PathBuf,
+        podman_ctx: &PodmanCtx,
+        image: &str,
+    ) -> ExecutedCommand {
+        let ro_store = podman_ctx
+            .ro_store()
+            .expect("Missing read-only store path in parallax_check()");
+        parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "check")
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use raster;
+    use crate::testutil::TestDir;
+    use std::fs;
 
     #[test]
     fn test_run_from_edf_command() {
@@ -650,7 +1311,8 @@ mod tests {
             parallax_mount_program: Some(PathBuf::from(
                 "/usr/local/sarus-test/parallax_mount_program",
             )),
-            ro_store: Some(PathBuf::from("/scratch/user/parallax/store")),
+            ro_stores: vec![PathBuf::from("/scratch/user/parallax/store")],
+            parallax_lock: LockConfig::default(),
             podman_env: None,
         };
 
@@ -660,6 +1322,7 @@ mod tests {
             detach: true,
             set_env: true,
             pidfile: Some(PathBuf::from("/tmp/test/pidfile")),
+            ..Default::default()
         };
 
         let edf_path = std::env::current_dir()
@@ -672,7 +1335,7 @@ mod tests {
         assert_eq!(cmd.get_program(), OsStr::new("/usr/bin/podman"));
 
         let args: Vec<&OsStr> = cmd.get_args().collect();
-        assert_eq!(args.len(), 40);
+        assert_eq!(args.len(), 42);
 
         let args_head: Vec<&OsStr> = vec![
             OsStr::new("--root"),
@@ -739,6 +1402,11 @@ mod tests {
                 OsStr::new("--annotation"),
                 OsStr::new("com.hooks.test2.enabled=false")
             ]));
+        assert!(args.windows(2).any(|w| w
+            == [
+                OsStr::new("--label"),
+                OsStr::new("sarus-suite.managed=true")
+            ]));
 
         // Image and container command must be positionally at the end of args
         let (_, args_tail) = args.split_at(args.len() - 2);
@@ -746,6 +1414,72 @@ mod tests {
         assert_eq!(args_tail[1], OsStr::new("bash"));
     }
 
+    #[test]
+    fn test_create_from_edf_command() {
+        let p_ctx = test_podman_ctx();
+        let c_ctx = ContainerCtx {
+            name: String::from("edf_test"),
+            interactive: true,
+            detach: true,
+            resources: ResourceLimits {
+                shm_size: Some(1 << 30),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let edf_path = std::env::current_dir()
+            .unwrap()
+            .join("tests/edf/run_from_edf_test.toml");
+        let edf =
+            raster::render(edf_path.to_string_lossy().into_owned()).expect("Failed rendering EDF");
+
+        let run = commands::run_from_edf(&edf, Some(&p_ctx), &c_ctx, ["bash"]);
+        let create = commands::create_from_edf(&edf, Some(&p_ctx), &c_ctx, ["bash"]);
+
+        // Same container, only without --detach which is up to start
+        let expected: Vec<&OsStr> = run
+            .get_args()
+            .filter(|&arg| arg != "--detach")
+            .map(|arg| {
+                if arg == "run" {
+                    OsStr::new("create")
+                } else {
+                    arg
+                }
+            })
+            .collect();
+        let args: Vec<&OsStr> = create.get_args().collect();
+        assert_eq!(args, expected);
+
+        assert_args(
+            &commands::start("edf_test", true, true, Some(&p_ctx)),
+            &[
+                &GLOBAL_ARGS[..],
+                &["start", "--attach", "--interactive", "edf_test"],
+            ]
+            .concat(),
+        );
+    }
+
+    #[test]
+    fn test_loggable_parallax_lock_failure() -> anyhow::Result<()> {
+        let root = TestDir::new("loggable");
+        // No lock directory can be created in a store which is not a directory
+        let ro_store = root.join("store");
+        fs::write(&ro_store, "")?;
+        let p_ctx = PodmanCtx {
+            ro_stores: vec![ro_store],
+            ..test_podman_ctx()
+        };
+
+        let executed = loggable::parallax_migrate(&PathBuf::from("/bin/true"), &p_ctx, "alpine");
+        assert!(executed.command.starts_with("/bin/true  --podmanRoot"));
+        assert!(!executed.output.status.success());
+        let stderr = String::from_utf8_lossy(&executed.output.stderr);
+        assert!(stderr.starts_with("Error: parallax migrate: cannot create"));
+        Ok(())
+    }
+
     #[test]
     fn test_parallax_command() {
         let p_ctx = PodmanCtx {
@@ -756,14 +1490,16 @@ mod tests {
             parallax_mount_program: Some(PathBuf::from(
                 "/usr/local/sarus-test/parallax_mount_program",
             )),
-            ro_store: Some(PathBuf::from("/scratch/user/parallax/store")),
+            ro_stores: vec![PathBuf::from("/scratch/user/parallax/store")],
+            parallax_lock: LockConfig::default(),
             podman_env: None,
         };
 
         let parallax_path = PathBuf::from("/usr/local/sarus-test/parallax");
         let image = String::from("ubuntu:24.04");
 
-        let cmd = commands::parallax(&parallax_path, &p_ctx, &image, "migrate");
+        let ro_store = p_ctx.ro_store().unwrap();
+        let cmd = commands::parallax(&parallax_path, &p_ctx, ro_store, &image, "migrate").unwrap();
 
         assert_eq!(cmd.get_program(), parallax_path);
 
@@ -774,11 +1510,470 @@ mod tests {
             OsStr::new("--podmanRoot"),
             OsStr::new(p_ctx.graphroot.as_deref().unwrap()),
             OsStr::new("--roStoragePath"),
-            OsStr::new(p_ctx.ro_store.as_deref().unwrap()),
+            OsStr::new(ro_store),
             OsStr::new("--migrate"),
             OsStr::new("--image"),
             OsStr::new(&image),
         ];
         assert_eq!(args, args_expected);
     }
+
+    fn test_podman_ctx() -> PodmanCtx {
+        PodmanCtx {
+            podman_path: PathBuf::from("/usr/bin/podman"),
+            module: Some(String::from("hpc")),
+            graphroot: Some(PathBuf::from("/dev/shm/sarus-test/graphroot")),
+            runroot: Some(PathBuf::from("/dev/shm/sarus-test/runroot")),
+            parallax_mount_program: Some(PathBuf::from(
+                "/usr/local/sarus-test/parallax_mount_program",
+            )),
+            ro_stores: vec![PathBuf::from("/scratch/user/parallax/store")],
+            parallax_lock: LockConfig::default(),
+            podman_env: None,
+        }
+    }
+
+    const GLOBAL_ARGS: [&str; 10] = [
+        "--root",
+        "/dev/shm/sarus-test/graphroot",
+        "--runroot",
+        "/dev/shm/sarus-test/runroot",
+        "--module",
+        "hpc",
+        "--storage-opt",
+        "additionalimagestore=/scratch/user/parallax/store",
+        "--storage-opt",
+        "mount_program=/usr/local/sarus-test/parallax_mount_program",
+    ];
+
+    fn assert_args(cmd: &Command, expected: &[&str]) {
+        assert_eq!(cmd.get_program(), OsStr::new("/usr/bin/podman"));
+        let args: Vec<&OsStr> = cmd.get_args().collect();
+        let expected: Vec<&OsStr> = expected.iter().map(OsStr::new).collect();
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_global_options_command() {
+        let p_ctx = test_podman_ctx();
+        let p_ctx = Some(&p_ctx);
+        fn with_globals<'a>(sub: &[&'a str]) -> Vec<&'a str> {
+            [&GLOBAL_ARGS[..], sub].concat()
+        }
+
+        assert_args(&commands::run(p_ctx), &with_globals(&["run"]));
+        assert_args(
+            &commands::pull("alpine:3.22", p_ctx),
+            &with_globals(&["pull", "alpine:3.22"]),
+        );
+        assert_args(
+            &commands::rmi("alpine:3.22", p_ctx),
+            &with_globals(&["rmi", "alpine:3.22"]),
+        );
+        assert_args(
+            &commands::rm("edf_test", p_ctx),
+            &with_globals(&["rm", "edf_test"]),
+        );
+        assert_args(
+            &commands::stop("edf_test", p_ctx),
+            &with_globals(&["stop", "edf_test"]),
+        );
+        assert_args(
+            &commands::image_exists("alpine:3.22", p_ctx),
+            &with_globals(&["image", "exists", "alpine:3.22"]),
+        );
+        assert_args(&commands::images(p_ctx), &with_globals(&["images"]));
+        assert_args(
+            &commands::inspect("edf_test", Some("{{.Id}}"), p_ctx),
+            &with_globals(&["--log-level=error", "inspect", "-f", "{{.Id}}", "edf_test"]),
+        );
+        assert_args(
+            &commands::info(Some("{{.Store.RunRoot}}"), p_ctx),
+            &with_globals(&["info", "-f", "{{.Store.RunRoot}}"]),
+        );
+        assert_args(&commands::version(p_ctx), &with_globals(&["version"]));
+        assert_args(
+            &commands::wait(
+                "edf_test",
+                &[WaitCondition::Stopped, WaitCondition::Healthy],
+                p_ctx,
+            ),
+            &with_globals(&[
+                "wait",
+                "--condition",
+                "stopped",
+                "--condition",
+                "healthy",
+                "edf_test",
+            ]),
+        );
+
+        // Without a context we fall back to plain `podman` and its own configuration
+        let cmd = commands::pull("alpine:3.22", None);
+        assert_eq!(cmd.get_program(), OsStr::new("podman"));
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["pull", "alpine:3.22"]);
+    }
+
+    #[test]
+    fn test_resource_limits_command() {
+        let limits = ResourceLimits {
+            cpus: Some(1.5),
+            memory: Some(4 * 1024 * 1024 * 1024),
+            pids_limit: Some(-1),
+            cpuset_cpus: Some(String::from("0-3,8")),
+            shm_size: Some(8 * 1024 * 1024 * 1024),
+            ulimits: vec![
+                Ulimit::unlimited("memlock"),
+                Ulimit::new("nofile", 1024, 4096),
+            ],
+        };
+
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::resource_args(&mut cmd, &limits);
+        assert_args(
+            &cmd,
+            &[
+                "--cpus",
+                "1.5",
+                "--memory",
+                "4294967296",
+                "--pids-limit",
+                "-1",
+                "--shm-size",
+                "8589934592",
+                "--cpuset-cpus",
+                "0-3,8",
+                "--ulimit",
+                "memlock=-1:-1",
+                "--ulimit",
+                "nofile=1024:4096",
+            ],
+        );
+
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::resource_args(&mut cmd, &ResourceLimits::default());
+        assert_args(&cmd, &[]);
+    }
+
+    #[test]
+    fn test_log_config_command() {
+        let log = LogConfig {
+            driver: Some(LogDriver::KFile),
+            path: Some(PathBuf::from("/scratch/user/job-42/rank0.log")),
+            max_size: Some(10 * 1024 * 1024),
+        };
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::log_args(&mut cmd, &log);
+        assert_args(
+            &cmd,
+            &[
+                "--log-driver",
+                "k8s-file",
+                "--log-opt",
+                "path=/scratch/user/job-42/rank0.log",
+                "--log-opt",
+                "max-size=10485760",
+            ],
+        );
+
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::log_args(&mut cmd, &LogConfig::default());
+        assert_args(&cmd, &[]);
+    }
+
+    #[test]
+    fn test_runtime_profile_command() {
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::profile_args(&mut cmd, &RuntimeProfile::hpc());
+        assert_args(
+            &cmd,
+            &[
+                "--network",
+                "host",
+                "--ipc",
+                "host",
+                "--pid",
+                "host",
+                "--cgroupns",
+                "host",
+                "--cgroups",
+                "disabled",
+                "--ulimit",
+                "host",
+            ],
+        );
+
+        let profile = RuntimeProfile {
+            network: Some(NamespaceMode::Empty),
+            pid: Some(NamespaceMode::Container(String::from("sidecar"))),
+            cgroups: Some(CgroupsMode::NoConmon),
+            ..RuntimeProfile::isolated()
+        };
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::profile_args(&mut cmd, &profile);
+        assert_args(
+            &cmd,
+            &[
+                "--network",
+                "none",
+                "--ipc",
+                "private",
+                "--pid",
+                "container:sidecar",
+                "--cgroupns",
+                "private",
+                "--cgroups",
+                "no-conmon",
+            ],
+        );
+
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::profile_args(&mut cmd, &RuntimeProfile::default());
+        assert_args(&cmd, &[]);
+    }
+
+    #[test]
+    fn test_contradictory_settings_are_rejected() {
+        let limited = ContainerCtx {
+            name: String::from("edf_test"),
+            resources: ResourceLimits {
+                memory: Some(1 << 30),
+                pids_limit: Some(100),
+                shm_size: Some(1 << 30),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(limited.validate().is_ok());
+
+        let hpc = ContainerCtx {
+            profile: RuntimeProfile::hpc(),
+            ..limited
+        };
+        let err = hpc.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "resource limits (memory, pids limit) cannot be enforced with cgroups disabled"
+        );
+
+        let ulimits = ContainerCtx {
+            profile: RuntimeProfile {
+                host_ulimits: true,
+                ..Default::default()
+            },
+            resources: ResourceLimits {
+                ulimits: vec![Ulimit::unlimited("memlock")],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(ulimits.validate().is_err());
+
+        // Rejected as podman does, without running it
+        let edf_path = std::env::current_dir()
+            .unwrap()
+            .join("tests/edf/run_from_edf_test.toml");
+        let edf =
+            raster::render(edf_path.to_string_lossy().into_owned()).expect("Failed rendering EDF");
+        let p_ctx = PodmanCtx {
+            podman_path: PathBuf::from("/nonexistent/podman"),
+            ..test_podman_ctx()
+        };
+        let (output, name) = run_from_edf_output(&edf, Some(&p_ctx), &hpc, ["true"]);
+        assert_eq!(name, hpc.name);
+        assert_eq!(output.status.code(), Some(125));
+        assert!(output.stderr.starts_with(b"Error: resource limits"));
+        assert!(create_from_edf(&edf, Some(&p_ctx), &hpc, ["true"]).is_err());
+    }
+
+    #[test]
+    fn test_identity_command() {
+        let identity = Identity {
+            user: Some(User::Id {
+                uid: 1000,
+                gid: Some(1000),
+            }),
+            group_add: vec![Group::Name(String::from("video")), Group::Id(27)],
+            passwd_entry: Some(String::from("$USERNAME:x:$UID:$GID::$HOME:/bin/bash")),
+            userns: Some(UserNsMode::Path(PathBuf::from("/proc/4242/ns/user"))),
+        };
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::identity_args(&mut cmd, &identity);
+        assert_args(
+            &cmd,
+            &[
+                "--user",
+                "1000:1000",
+                "--group-add",
+                "video",
+                "--group-add",
+                "27",
+                "--passwd-entry",
+                "$USERNAME:x:$UID:$GID::$HOME:/bin/bash",
+                "--userns",
+                "ns:/proc/4242/ns/user",
+            ],
+        );
+
+        let identity = Identity {
+            user: Some(User::Name {
+                user: String::from("nobody"),
+                group: None,
+            }),
+            group_add: vec![Group::KeepGroups],
+            passwd_entry: None,
+            userns: Some(UserNsMode::keep_id()),
+        };
+        let mut cmd = Command::new("/usr/bin/podman");
+        commands::identity_args(&mut cmd, &identity);
+        assert_args(
+            &cmd,
+            &[
+                "--user",
+                "nobody",
+                "--group-add",
+                "keep-groups",
+                "--userns",
+                "keep-id",
+            ],
+        );
+        assert!(identity.check().is_ok());
+
+        let identity = Identity {
+            group_add: vec![Group::KeepGroups, Group::Name(String::from("video"))],
+            ..identity
+        };
+        let err = ContainerCtx {
+            identity,
+            ..Default::default()
+        }
+        .validate()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "keep-groups cannot be combined with other supplementary groups"
+        );
+
+        let keep_id = UserNsMode::KeepId {
+            uid: Some(1000),
+            gid: Some(100),
+        };
+        assert_eq!(keep_id.to_arg(), "keep-id:uid=1000,gid=100");
+        assert_eq!(UserNsMode::keep_id().to_arg(), "keep-id");
+    }
+
+    #[test]
+    fn test_multiple_ro_stores_command() {
+        let mut p_ctx = test_podman_ctx();
+        p_ctx
+            .ro_stores
+            .push(PathBuf::from("/scratch/project/parallax/store"));
+
+        let mut expected = GLOBAL_ARGS.to_vec();
+        expected.splice(
+            8..8,
+            [
+                "--storage-opt",
+                "additionalimagestore=/scratch/project/parallax/store",
+            ],
+        );
+        expected.push("images");
+        assert_args(&commands::images(Some(&p_ctx)), &expected);
+
+        // Parallax can target any of the stores, not only the default one
+        let parallax_path = PathBuf::from("/usr/local/sarus-test/parallax");
+        let cmd = commands::parallax(
+            &parallax_path,
+            &p_ctx,
+            &p_ctx.ro_stores[1],
+            "ubuntu:24.04",
+            "migrate",
+        )
+        .unwrap();
+        let args: Vec<&OsStr> = cmd.get_args().collect();
+        assert_eq!(
+            args[2..4],
+            ["--roStoragePath", "/scratch/project/parallax/store"]
+        );
+    }
+
+    #[test]
+    fn test_conf_files() -> anyhow::Result<()> {
+        let p_ctx = test_podman_ctx()
+            .with_env("PARALLAX_MP_SQUASHFUSE_FLAG", "-o uid=432,gid=123")
+            .with_env("PARALLAX_MP_SQUASHFUSE_CMD", "/usr/bin/squashfuse_ll");
+
+        let storage_conf = p_ctx.storage_conf()?;
+        let storage_expected = [
+            "[storage]",
+            "driver = \"overlay\"",
+            "graphroot = \"/dev/shm/sarus-test/graphroot\"",
+            "runroot = \"/dev/shm/sarus-test/runroot\"",
+            "[storage.options]",
+            "additionalimagestores = [\"/scratch/user/parallax/store\"]",
+            "[storage.options.overlay]",
+            "mount_program = \"/usr/local/sarus-test/parallax_mount_program\"",
+        ];
+        let storage_lines: Vec<&str> = storage_conf
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+        assert_eq!(storage_lines, storage_expected);
+
+        let containers_conf = p_ctx.containers_conf()?;
+        assert!(containers_conf.contains(
+            "env = [\"PARALLAX_MP_SQUASHFUSE_CMD=/usr/bin/squashfuse_ll\", \
+             \"PARALLAX_MP_SQUASHFUSE_FLAG=-o uid=432,gid=123\"]"
+        ));
+
+        let conf_dir = TestDir::new("conf");
+        let p_ctx = p_ctx.with_conf_files(conf_dir.path())?;
+        let envs = p_ctx.podman_env.as_ref().unwrap();
+        let storage_path = envs.get(OsStr::new(CONTAINERS_STORAGE_CONF_ENV)).unwrap();
+        assert_eq!(std::fs::read_to_string(storage_path)?, storage_conf);
+        let conf_path = envs.get(OsStr::new(CONTAINERS_CONF_ENV)).unwrap();
+        assert_eq!(std::fs::read_to_string(conf_path)?, containers_conf);
+
+        // Regenerating must not leak our own env vars into the engine env
+        assert_eq!(p_ctx.containers_conf()?, containers_conf);
+        Ok(())
+    }
+
+    #[test]
+    fn test_image_exists_from_storage() -> anyhow::Result<()> {
+        let root = TestDir::new("exists");
+        let graphroot = root.join("graphroot");
+        let ro_store = root.join("store");
+        std::fs::create_dir_all(graphroot.join("overlay-images"))?;
+        std::fs::create_dir_all(ro_store.join("overlay-images"))?;
+        std::fs::write(
+            ro_store.join("overlay-images/images.json"),
+            r#"[{"id":"9234e8fb04c47cfe0f49931e4ac7eb76fa904e33b7f8576aec0501c085f02516",
+                 "names":["docker.io/library/alpine:3.22"]}]"#,
+        )?;
+
+        // `false` stands for podman: answers coming from it are always negative
+        let mut p_ctx = test_podman_ctx();
+        p_ctx.podman_path = PathBuf::from("/bin/false");
+        p_ctx.graphroot = Some(graphroot);
+        p_ctx.ro_stores = vec![ro_store];
+        let p_ctx = Some(&p_ctx);
+
+        assert!(image_exists("alpine:3.22", p_ctx));
+        assert!(image_exists("9234e8fb04c4", p_ctx));
+        assert!(!image_exists("docker.io/library/alpine:3.21", p_ctx));
+
+        // Misses are left to podman, which may find the image in a store of storage.conf, here
+        // answering positively
+        let mut p_ctx = test_podman_ctx();
+        p_ctx.podman_path = PathBuf::from("/bin/true");
+        p_ctx.graphroot = Some(root.join("graphroot"));
+        assert!(image_exists("alpine:3.21", Some(&p_ctx)));
+        assert!(image_exists("docker.io/library/alpine:3.21", Some(&p_ctx)));
+
+        // Unknown layout
+        p_ctx.graphroot = Some(root.join("vfs"));
+        assert!(image_exists("docker.io/library/alpine:3.21", Some(&p_ctx)));
+        Ok(())
+    }
 }
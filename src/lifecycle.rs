@@ -0,0 +1,546 @@
+// Lifecycle operations on existing containers. Stop and removal can be batched, with
+// per-container results.
+use crate::wait::poll;
+use crate::{PodmanCtx, checked_output, commands};
+use std::fmt;
+use std::process::Command;
+use std::time::Duration;
+
+// Used by podman unless the container was created with --stop-timeout
+const DEFAULT_STOP_TIMEOUT: u32 = 10;
+
+#[derive(Clone, Debug, Default)]
+pub struct StopOptions {
+    // Seconds granted to the container to stop before it is killed, podman's default if None
+    pub timeout: Option<u32>,
+    // Signal sent instead of the stop signal the container was created with, e.g. "SIGUSR1"
+    // for applications checkpointing on it. SIGKILL still follows after `timeout`.
+    pub signal: Option<String>,
+    // Missing containers are not an error
+    pub ignore: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RmOptions {
+    // Stop running containers first, killing them after `timeout`
+    pub force: bool,
+    pub ignore: bool,
+    // Also remove the anonymous volumes of the containers
+    pub volumes: bool,
+    // Seconds granted to a running container to stop, when forcing
+    pub timeout: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContainerError {
+    NotFound { container: String },
+    // e.g. removing a running container without force
+    InvalidState { container: String, message: String },
+    Engine { container: String, message: String },
+}
+
+impl ContainerError {
+    // Classify an error reported by podman for `container`
+    pub(crate) fn from_podman(container: &str, message: &str) -> Self {
+        let container = container.to_string();
+        let message: Vec<&str> = message
+            .trim()
+            .lines()
+            .map(|l| l.trim_start_matches("Error: "))
+            .collect();
+        let message = message.join("\n");
+        if message.contains("no such container") {
+            Self::NotFound { container }
+        } else if message.contains("container state improper") {
+            Self::InvalidState { container, message }
+        } else {
+            Self::Engine { container, message }
+        }
+    }
+
+    pub fn container(&self) -> &str {
+        match self {
+            Self::NotFound { container }
+            | Self::InvalidState { container, .. }
+            | Self::Engine { container, .. } => container,
+        }
+    }
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { container } => write!(f, "no container with name or ID {container}"),
+            Self::InvalidState { container, message } | Self::Engine { container, message } => {
+                write!(f, "container {container}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+#[derive(Debug)]
+pub struct ContainerResult {
+    // Name or ID as given, or full ID when selected by filters
+    pub container: String,
+    pub result: Result<(), ContainerError>,
+}
+
+pub fn stop_containers(
+    containers: &[&str],
+    options: &StopOptions,
+    podman_ctx: Option<&PodmanCtx>,
+) -> Vec<ContainerResult> {
+    if containers.is_empty() {
+        return Vec::new();
+    }
+    let Some(signal) = &options.signal else {
+        return batch(
+            commands::stop_with(containers, options, podman_ctx),
+            containers,
+        );
+    };
+
+    // `podman stop` always sends the stop signal set at creation: deliver the custom one with
+    // kill, wait for the containers to exit, then stop those still running right away
+    let killed = batch(
+        commands::kill(containers, Some(signal), podman_ctx),
+        containers,
+    );
+    let mut results = Vec::new();
+    let mut remaining = Vec::new();
+    for killed in killed {
+        match killed.result {
+            Err(ContainerError::NotFound { .. }) if options.ignore => {
+                results.push(ContainerResult {
+                    container: killed.container,
+                    result: Ok(()),
+                })
+            }
+            err @ Err(ContainerError::NotFound { .. }) => results.push(ContainerResult {
+                container: killed.container,
+                result: err,
+            }),
+            // Already stopped containers are not signaled, stopping them is a no-op
+            _ => remaining.push(killed.container),
+        }
+    }
+
+    // A failing `podman ps` counts as the containers still running, so that they get the whole
+    // grace period rather than being stopped right away. Timing out leaves them to the immediate
+    // stop below, the poll cannot fail otherwise.
+    let timeout = options.timeout.unwrap_or(DEFAULT_STOP_TIMEOUT);
+    let _ = poll(Duration::from_secs(timeout.into()), || {
+        let Ok(running) = running_containers(podman_ctx) else {
+            return Ok(None);
+        };
+        let any_running = remaining
+            .iter()
+            .any(|cnt| running.iter().any(|r| r.matches(cnt)));
+        Ok((!any_running).then_some(()))
+    });
+
+    let remaining: Vec<&str> = remaining.iter().map(String::as_str).collect();
+    let immediate = StopOptions {
+        timeout: Some(0),
+        signal: None,
+        ignore: options.ignore,
+    };
+    results.extend(batch(
+        commands::stop_with(&remaining, &immediate, podman_ctx),
+        &remaining,
+    ));
+    results
+}
+
+// Stop the running containers matching all of `filters` (as taken by `podman ps --filter`,
+// e.g. "label=sarus-suite.slurm-job-id=42")
+pub fn stop_all(
+    filters: &[&str],
+    options: &StopOptions,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<Vec<ContainerResult>> {
+    let ids = select(filters, false, podman_ctx)?;
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    Ok(stop_containers(&ids, options, podman_ctx))
+}
+
+pub fn rm_containers(
+    containers: &[&str],
+    options: &RmOptions,
+    podman_ctx: Option<&PodmanCtx>,
+) -> Vec<ContainerResult> {
+    if containers.is_empty() {
+        return Vec::new();
+    }
+    batch(
+        commands::rm_with(containers, options, podman_ctx),
+        containers,
+    )
+}
+
+// Remove the containers, running or not, matching all of `filters`
+pub fn rm_all(
+    filters: &[&str],
+    options: &RmOptions,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<Vec<ContainerResult>> {
+    let ids = select(filters, true, podman_ctx)?;
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    Ok(rm_containers(&ids, options, podman_ctx))
+}
+
+// Send `signal` (SIGKILL if None) to the main process of the container, e.g. SIGTERM to
+// preempt it or SIGUSR1 to have it checkpoint
+pub fn kill(
+    name: &str,
+    signal: Option<&str>,
+    podman_ctx: Option<&PodmanCtx>,
+) -> Result<(), ContainerError> {
+    single(commands::kill(&[name], signal, podman_ctx), name)
+}
+
+// Start a created container in the background, see also start_attached()
+pub fn start(name: &str, podman_ctx: Option<&PodmanCtx>) -> Result<(), ContainerError> {
+    single(commands::start(name, false, false, podman_ctx), name)
+}
+
+// Freeze all the processes of the container (cgroup freezer)
+pub fn pause(name: &str, podman_ctx: Option<&PodmanCtx>) -> Result<(), ContainerError> {
+    single(commands::pause(name, podman_ctx), name)
+}
+
+pub fn unpause(name: &str, podman_ctx: Option<&PodmanCtx>) -> Result<(), ContainerError> {
+    single(commands::unpause(name, podman_ctx), name)
+}
+
+// Stop the container, killing it after `timeout` seconds as for stop, and start it again
+pub fn restart(
+    name: &str,
+    timeout: Option<u32>,
+    podman_ctx: Option<&PodmanCtx>,
+) -> Result<(), ContainerError> {
+    single(commands::restart(name, timeout, podman_ctx), name)
+}
+
+fn single(cmd: Command, name: &str) -> Result<(), ContainerError> {
+    batch(cmd, &[name]).pop().map_or(Ok(()), |res| res.result)
+}
+
+// Run a podman command over `containers` and attribute the errors it reports to each of them.
+// Podman prints the containers it handled; errors naming none of the others (e.g. naming the ID
+// of a container given by name) are reported for all of them.
+pub(crate) fn batch(mut cmd: Command, containers: &[&str]) -> Vec<ContainerResult> {
+    let output = cmd.output().expect("Failed to execute command");
+    if output.status.success() {
+        return containers
+            .iter()
+            .map(|&cnt| ContainerResult {
+                container: cnt.to_string(),
+                result: Ok(()),
+            })
+            .collect();
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let handled: Vec<&str> = stdout.lines().map(str::trim).collect();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let errors: Vec<&str> = stderr
+        .lines()
+        .map(|l| l.trim().trim_start_matches("Error: "))
+        .filter(|l| !l.is_empty())
+        .collect();
+    let unattributed: Vec<&str> = errors
+        .iter()
+        .copied()
+        .filter(|e| !containers.iter().any(|cnt| names(e, cnt)))
+        .collect();
+
+    containers
+        .iter()
+        .map(|&cnt| {
+            let error = if handled.contains(&cnt) {
+                None
+            } else if let Some(error) = errors.iter().find(|e| names(e, cnt)) {
+                Some(error.to_string())
+            } else if !unattributed.is_empty() {
+                Some(unattributed.join("\n"))
+            } else if errors.is_empty() {
+                Some(format!("podman exited with {}", output.status))
+            } else {
+                // Failed because of another container
+                None
+            };
+            ContainerResult {
+                container: cnt.to_string(),
+                result: error.map_or(Ok(()), |e| Err(ContainerError::from_podman(cnt, &e))),
+            }
+        })
+        .collect()
+}
+
+// Whether `error` names `container` as a whole, e.g. not "rank1" within "rank10"
+fn names(error: &str, container: &str) -> bool {
+    error
+        .split(|c: char| !(c.is_ascii_alphanumeric() || "_.-".contains(c)))
+        // A name ending a sentence
+        .any(|token| token == container || token.strip_suffix('.') == Some(container))
+}
+
+struct RunningContainer {
+    id: String,
+    names: Vec<String>,
+}
+
+impl RunningContainer {
+    fn matches(&self, name_or_id: &str) -> bool {
+        self.id.starts_with(name_or_id) || self.names.iter().any(|n| n == name_or_id)
+    }
+}
+
+fn running_containers(podman_ctx: Option<&PodmanCtx>) -> anyhow::Result<Vec<RunningContainer>> {
+    let output = checked_output(
+        &mut commands::ps(&[], false, "{{.ID}} {{.Names}}", podman_ctx),
+        "podman ps",
+    )?;
+    Ok(str::from_utf8(&output.stdout)?
+        .lines()
+        .filter_map(|l| {
+            let (id, names) = l.trim().split_once(' ')?;
+            Some(RunningContainer {
+                id: id.to_string(),
+                names: names.split(',').map(String::from).collect(),
+            })
+        })
+        .collect())
+}
+
+// Full IDs of the containers matching `filters`, only running ones unless `all`
+fn select(
+    filters: &[&str],
+    all: bool,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<Vec<String>> {
+    let output = checked_output(
+        &mut commands::ps(filters, all, "{{.ID}}", podman_ctx),
+        "podman ps",
+    )?;
+    Ok(str::from_utf8(&output.stdout)?
+        .lines()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+    use std::fs;
+    use std::time::Instant;
+
+    #[test]
+    fn test_batch_stop_and_rm() -> anyhow::Result<()> {
+        // "gone" does not exist and "busy" is running, every other container is stopped
+        let podman = FakePodman::new(
+            "lifecycle",
+            r#"case "$1" in
+    ps)
+        [ -e "$test_dir/ps_broken" ] && exit 125
+        case "$*" in
+            *label=job=42*) printf 'aaaa1111\nbbbb2222\n' ;;
+            *) echo "cccc3333 busy" ;;
+        esac ;;
+    stop|kill|rm)
+        op=$1
+        status=0
+        shift
+        for cnt in "$@"; do
+            case "$cnt" in
+                -*|[0-9]*|SIG*) ;;
+                gone)
+                    echo "Error: no container with name or ID \"gone\" found: no such container" >&2
+                    status=125 ;;
+                busy)
+                    if [ "$op" = rm ]; then
+                        echo "Error: cannot remove container cccc3333 as it is running - running or paused containers cannot be removed without force: container state improper" >&2
+                        status=2
+                    else
+                        echo "$cnt"
+                    fi ;;
+                *) echo "$cnt" ;;
+            esac
+        done
+        exit $status ;;
+esac
+"#,
+        );
+        let p_ctx = podman.ctx();
+
+        let stop = StopOptions {
+            timeout: Some(3),
+            ..Default::default()
+        };
+        let results = stop_containers(&["done", "gone"], &stop, Some(&p_ctx));
+        assert_eq!(results[0].container, "done");
+        assert!(results[0].result.is_ok());
+        assert_eq!(
+            results[1].result,
+            Err(ContainerError::NotFound {
+                container: String::from("gone")
+            })
+        );
+
+        // "busy" keeps running after the signal and gets stopped after the timeout
+        let signaled = StopOptions {
+            timeout: Some(0),
+            signal: Some(String::from("SIGUSR1")),
+            ignore: true,
+        };
+        let results = stop_containers(&["busy", "gone"], &signaled, Some(&p_ctx));
+        assert!(results.iter().all(|r| r.result.is_ok()));
+
+        let rm = RmOptions {
+            volumes: true,
+            ..Default::default()
+        };
+        let results = rm_containers(&["done", "busy"], &rm, Some(&p_ctx));
+        assert!(results[0].result.is_ok());
+        assert!(matches!(
+            &results[1].result,
+            Err(ContainerError::InvalidState { container, .. }) if container == "busy"
+        ));
+
+        let forced = RmOptions {
+            force: true,
+            ignore: true,
+            volumes: false,
+            timeout: Some(5),
+        };
+        let results = rm_all(&["label=job=42"], &forced, Some(&p_ctx))?;
+        let removed: Vec<&str> = results.iter().map(|r| r.container.as_str()).collect();
+        assert_eq!(removed, ["aaaa1111", "bbbb2222"]);
+
+        assert_eq!(
+            podman.calls(),
+            [
+                "stop --time 3 done gone",
+                "kill --signal SIGUSR1 busy gone",
+                "ps --no-trunc --format {{.ID}} {{.Names}}",
+                "stop --ignore --time 0 busy",
+                "rm --volumes done busy",
+                "ps --all --no-trunc --format {{.ID}} --filter label=job=42",
+                "rm --force --ignore --time 5 aaaa1111 bbbb2222",
+            ]
+        );
+
+        // A failing ps does not cut the grace period short
+        fs::write(podman.dir().join("ps_broken"), "")?;
+        let graced = StopOptions {
+            timeout: Some(1),
+            ..signaled
+        };
+        let start = Instant::now();
+        let results = stop_containers(&["busy"], &graced, Some(&p_ctx));
+        assert!(results[0].result.is_ok());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        let calls = podman.calls();
+        assert!(calls.iter().filter(|c| c.starts_with("ps ")).count() > 3);
+        assert_eq!(calls.last().unwrap(), "stop --ignore --time 0 busy");
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_attribution() {
+        let podman = FakePodman::new(
+            "batch",
+            r#"echo "Error: no container with name or ID \"rank10\" found: no such container" >&2
+echo "Error: cannot remove container rank2. It is running" >&2
+exit 125
+"#,
+        );
+        let p_ctx = podman.ctx();
+
+        let results = rm_containers(
+            &["rank1", "rank10", "rank2"],
+            &RmOptions::default(),
+            Some(&p_ctx),
+        );
+        // Not removed because of the others
+        assert_eq!(results[0].result, Ok(()));
+        assert_eq!(
+            results[1].result,
+            Err(ContainerError::NotFound {
+                container: String::from("rank10")
+            })
+        );
+        assert!(matches!(
+            &results[2].result,
+            Err(ContainerError::Engine { message, .. }) if message.starts_with("cannot remove")
+        ));
+    }
+
+    #[test]
+    fn test_lifecycle_operations() -> anyhow::Result<()> {
+        let podman = FakePodman::new(
+            "ops",
+            r#"case "$*" in
+    *gone)
+        echo "Error: no container with name or ID \"gone\" found: no such container" >&2
+        exit 125 ;;
+    "unpause rank0")
+        echo "Error: \"rank0\" is not paused, can't unpause: container state improper" >&2
+        exit 125 ;;
+esac
+echo "$2"
+"#,
+        );
+        let p_ctx = podman.ctx();
+
+        kill("rank0", Some("SIGUSR1"), Some(&p_ctx))?;
+        kill("rank0", None, Some(&p_ctx))?;
+        pause("rank0", Some(&p_ctx))?;
+        restart("rank0", Some(2), Some(&p_ctx))?;
+        assert_eq!(
+            pause("gone", Some(&p_ctx)),
+            Err(ContainerError::NotFound {
+                container: String::from("gone")
+            })
+        );
+        assert_eq!(
+            unpause("rank0", Some(&p_ctx)),
+            Err(ContainerError::InvalidState {
+                container: String::from("rank0"),
+                message: String::from(
+                    "\"rank0\" is not paused, can't unpause: container state improper"
+                ),
+            })
+        );
+
+        let executed = crate::loggable::unpause("gone", Some(&p_ctx));
+        assert_eq!(
+            executed.command,
+            format!("{}  unpause gone", p_ctx.podman_path.display())
+        );
+        assert!(matches!(
+            executed.container_result("gone"),
+            Err(ContainerError::NotFound { .. })
+        ));
+
+        assert_eq!(
+            podman.calls(),
+            [
+                "kill --signal SIGUSR1 rank0",
+                "kill rank0",
+                "pause rank0",
+                "restart --time 2 rank0",
+                "pause gone",
+                "unpause rank0",
+                "unpause gone",
+            ]
+        );
+        Ok(())
+    }
+}
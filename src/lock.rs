@@ -0,0 +1,235 @@
+// Cross-process locking of the images of a read-only store, so that parallax runs started
+// concurrently for the same image (e.g. by many job steps on different nodes) are serialized.
+use crate::storage;
+use anyhow::Context;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+const LOCKS_DIR: &str = ".parallax-locks";
+
+#[derive(Clone, Debug)]
+pub struct LockConfig {
+    // How long to wait for the holder of the lock to be done with the same image
+    pub timeout: Duration,
+    // A lock whose file was not refreshed for this long is considered left behind, e.g. by a
+    // crashed node whose flock never got released by the shared filesystem.
+    // Holders refresh their lock file several times per period.
+    pub stale_after: Duration,
+    pub poll_interval: Duration,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30 * 60),
+            stale_after: Duration::from_secs(5 * 60),
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+// Exclusive lock on an image of a read-only store, released when dropped
+pub struct ImageLock {
+    path: PathBuf,
+    heartbeat: Option<(Sender<()>, JoinHandle<()>)>,
+    // Closing the file releases the flock
+    _file: File,
+}
+
+impl ImageLock {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn acquired(mut file: File, path: PathBuf, config: &LockConfig) -> anyhow::Result<Self> {
+        // Informative only, to name the holder in timeout errors
+        file.set_len(0)?;
+        writeln!(file, "pid {} on {}", std::process::id(), hostname())?;
+
+        let touch = file.try_clone()?;
+        let interval = config.stale_after / 3;
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let _ = touch.set_modified(SystemTime::now());
+            }
+        });
+
+        Ok(Self {
+            path,
+            heartbeat: Some((stop, handle)),
+            _file: file,
+        })
+    }
+}
+
+impl Drop for ImageLock {
+    fn drop(&mut self) {
+        if let Some((stop, handle)) = self.heartbeat.take() {
+            drop(stop);
+            let _ = handle.join();
+        }
+    }
+}
+
+// Lock `image` in `ro_store`, waiting for a concurrent holder up to `config.timeout`.
+// References to the same image are normalized to share the lock, e.g. "alpine" and
+// "docker.io/library/alpine:latest".
+pub fn lock_image(ro_store: &Path, image: &str, config: &LockConfig) -> anyhow::Result<ImageLock> {
+    let dir = ro_store.join(LOCKS_DIR);
+    fs::create_dir_all(&dir).with_context(|| format!("cannot create {}", dir.display()))?;
+    let path = dir.join(lock_file_name(image));
+    let start = Instant::now();
+
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("cannot open {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {
+                // The file may have been removed as stale between open() and try_lock()
+                if is_current(&file, &path)? {
+                    return ImageLock::acquired(file, path, config);
+                }
+                continue;
+            }
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("cannot lock {}", path.display()));
+            }
+        }
+
+        let age = file.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age >= config.stale_after && is_current(&file, &path)? {
+            // The holder, if still alive, keeps its lock on the unlinked file while
+            // contenders race for a fresh one
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => continue,
+            }
+        }
+
+        if start.elapsed() >= config.timeout {
+            let holder = fs::read_to_string(&path).unwrap_or_default();
+            anyhow::bail!(
+                "timed out after {:?} waiting for lock {} held by {}",
+                config.timeout,
+                path.display(),
+                holder.trim()
+            );
+        }
+        thread::sleep(config.poll_interval);
+    }
+}
+
+// Same as lock_image(), for operations only reading the store: a store the caller cannot write
+// to, e.g. a site store only its administrators change, is read without a lock
+pub(crate) fn lock_image_if_writable(
+    ro_store: &Path,
+    image: &str,
+    config: &LockConfig,
+) -> anyhow::Result<Option<ImageLock>> {
+    match lock_image(ro_store, image, config) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e) if read_only(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_only(err: &anyhow::Error) -> bool {
+    err.root_cause()
+        .downcast_ref::<io::Error>()
+        .is_some_and(|e| {
+            matches!(
+                e.kind(),
+                ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+            )
+        })
+}
+
+// Whether `file` is still the one at `path`
+fn is_current(file: &File, path: &Path) -> anyhow::Result<bool> {
+    let opened = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(opened.dev() == current.dev() && opened.ino() == current.ino()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn lock_file_name(image: &str) -> String {
+    let mut name: String = storage::normalize_reference(image)
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    name.push_str(".lock");
+    name
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| String::from("unknown host"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestDir;
+
+    #[test]
+    fn test_lock_timeout_and_stale() -> anyhow::Result<()> {
+        let dir = TestDir::new("image-lock");
+        let store = dir.path();
+        let config = LockConfig {
+            timeout: Duration::from_millis(300),
+            stale_after: Duration::from_secs(60),
+            poll_interval: Duration::from_millis(50),
+        };
+
+        let held = lock_image(store, "alpine", &config)?;
+        assert!(
+            held.path()
+                .ends_with("docker.io_library_alpine_latest.lock")
+        );
+
+        // Same image under another name: must wait for the holder and time out
+        let err = lock_image(store, "docker.io/library/alpine:latest", &config)
+            .err()
+            .expect("lock acquired twice");
+        assert!(err.to_string().contains("timed out"));
+
+        // Released on drop
+        drop(held);
+        let relocked = lock_image(store, "alpine", &config)?;
+
+        // A holder that stopped refreshing its lock (no heartbeat) is considered dead
+        let stale = File::open(relocked.path())?;
+        drop(relocked);
+        stale.try_lock()?;
+        let old = SystemTime::now() - Duration::from_secs(120);
+        OpenOptions::new()
+            .write(true)
+            .open(
+                store
+                    .join(LOCKS_DIR)
+                    .join("docker.io_library_alpine_latest.lock"),
+            )?
+            .set_modified(old)?;
+        let _broken = lock_image(store, "alpine", &config)?;
+        Ok(())
+    }
+}
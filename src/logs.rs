@@ -0,0 +1,250 @@
+// Output of containers, as kept by their log driver
+use crate::{PodmanCtx, checked_output, commands};
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+// Where the output of a container goes, podman's defaults if unset
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogConfig {
+    pub driver: Option<LogDriver>,
+    // Log file of the k8s-file driver, e.g. in the job directory of the user
+    pub path: Option<PathBuf>,
+    // Bytes after which the k8s-file log is rotated
+    pub max_size: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogDriver {
+    KFile,
+    Journald,
+    // Nothing is kept, logs() has nothing to return
+    Disabled,
+    // The output goes straight to the stdio of podman, for attached runs only.
+    // Nothing is kept either.
+    Passthrough,
+}
+
+impl LogDriver {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::KFile => "k8s-file",
+            Self::Journald => "journald",
+            Self::Disabled => "none",
+            Self::Passthrough => "passthrough",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LogsOptions {
+    // Timestamp or duration before now, as taken by podman, e.g. "2025-06-01T10:00:00Z", "10m"
+    pub since: Option<String>,
+    pub until: Option<String>,
+    // Only the last lines
+    pub tail: Option<u64>,
+    // Prefix lines with their RFC3339 timestamp
+    pub timestamps: bool,
+}
+
+pub struct ContainerLogs {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogSource {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogLine {
+    pub source: LogSource,
+    // Without the line terminator
+    pub line: Vec<u8>,
+}
+
+pub fn logs(
+    name: &str,
+    options: &LogsOptions,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<ContainerLogs> {
+    let output = checked_output(
+        &mut commands::logs(name, options, false, podman_ctx),
+        "podman logs",
+    )?;
+    Ok(ContainerLogs {
+        stdout: output.stdout,
+        stderr: output.stderr,
+    })
+}
+
+// Lines of the container as they are written, until it exits or the follower is dropped.
+// Lines of each stream keep their order, but not across streams. Errors of podman come as
+// stderr lines too, see LogFollower::wait() to tell them apart.
+pub fn follow_logs(
+    name: &str,
+    options: &LogsOptions,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<LogFollower> {
+    let mut child = commands::logs(name, options, true, podman_ctx)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let (tx, lines) = mpsc::channel();
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let readers = [
+        (LogSource::Stdout, Box::new(stdout) as Box<dyn Read + Send>),
+        (LogSource::Stderr, Box::new(stderr)),
+    ]
+    .into_iter()
+    .map(|(source, pipe)| {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(pipe).split(b'\n') {
+                let Ok(mut line) = line else { break };
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                if tx.send(LogLine { source, line }).is_err() {
+                    break;
+                }
+            }
+        })
+    })
+    .collect();
+
+    Ok(LogFollower {
+        child,
+        lines,
+        readers,
+    })
+}
+
+pub struct LogFollower {
+    child: Child,
+    lines: Receiver<LogLine>,
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl LogFollower {
+    // Wait for podman to end, i.e. for the container to exit, and fail if podman did, e.g.
+    // because the container does not exist. Lines not read yet are dropped.
+    pub fn wait(mut self) -> anyhow::Result<ExitStatus> {
+        let status = self.child.wait()?;
+        for reader in self.readers.drain(..) {
+            let _ = reader.join();
+        }
+        if !status.success() {
+            // The error of podman is the last it printed, unless already read
+            let error = self
+                .lines
+                .try_iter()
+                .filter(|l| l.source == LogSource::Stderr)
+                .last()
+                .map(|l| String::from_utf8_lossy(&l.line).into_owned());
+            match error {
+                Some(error) => anyhow::bail!("podman logs failed: {error}"),
+                None => anyhow::bail!("podman logs exited with {status}"),
+            }
+        }
+        Ok(status)
+    }
+}
+
+impl Iterator for LogFollower {
+    type Item = LogLine;
+
+    fn next(&mut self) -> Option<LogLine> {
+        self.lines.recv().ok()
+    }
+}
+
+impl Drop for LogFollower {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        for reader in self.readers.drain(..) {
+            let _ = reader.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+
+    #[test]
+    fn test_logs() -> anyhow::Result<()> {
+        let podman = FakePodman::new(
+            "logs",
+            r#"case "$*" in
+    *gone)
+        echo "Error: no container with name or ID \"gone\" found: no such container" >&2
+        exit 125 ;;
+esac
+echo "rank 0 started"
+echo "warning: low memory" >&2
+echo "rank 0 done"
+"#,
+        );
+        let p_ctx = podman.ctx();
+
+        let options = LogsOptions {
+            since: Some(String::from("10m")),
+            tail: Some(100),
+            timestamps: true,
+            ..Default::default()
+        };
+        let logs = logs("rank0", &options, Some(&p_ctx))?;
+        assert_eq!(logs.stdout, b"rank 0 started\nrank 0 done\n");
+        assert_eq!(logs.stderr, b"warning: low memory\n");
+
+        let mut follower = follow_logs("rank0", &LogsOptions::default(), Some(&p_ctx))?;
+        let mut lines: Vec<LogLine> = follower.by_ref().collect();
+        assert!(follower.wait()?.success());
+        lines.sort_by_key(|l| l.source == LogSource::Stderr);
+        assert_eq!(
+            lines,
+            [
+                LogLine {
+                    source: LogSource::Stdout,
+                    line: b"rank 0 started".to_vec()
+                },
+                LogLine {
+                    source: LogSource::Stdout,
+                    line: b"rank 0 done".to_vec()
+                },
+                LogLine {
+                    source: LogSource::Stderr,
+                    line: b"warning: low memory".to_vec()
+                },
+            ]
+        );
+
+        // Not an empty log
+        let follower = follow_logs("gone", &LogsOptions::default(), Some(&p_ctx))?;
+        let err = follower.wait().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "podman logs failed: Error: no container with name or ID \"gone\" found: \
+             no such container"
+        );
+
+        assert_eq!(
+            podman.calls(),
+            [
+                "logs --since 10m --tail 100 --timestamps rank0",
+                "logs --follow rank0",
+                "logs --follow gone"
+            ]
+        );
+        Ok(())
+    }
+}
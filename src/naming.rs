@@ -0,0 +1,219 @@
+// What to do when the name of a new container is taken, e.g. by a container left behind by a
+// previous job step on a node that rebooted
+use crate::{ContainerCtx, PodmanCtx, checked, commands, owned_args};
+use raster::EDF;
+use std::collections::hash_map::RandomState;
+use std::ffi::OsStr;
+use std::hash::BuildHasher;
+use std::io;
+use std::process::{Command, ExitStatus, Output};
+use std::time::SystemTime;
+
+// Attempts at a free name, the plain name included, before reporting the conflict
+const MAX_ATTEMPTS: u32 = 8;
+// Podman exits with this code when it fails, rather than the container
+const ENGINE_ERROR_CODE: i32 = 125;
+// End of podman's error for a taken name, which the output of a container cannot be confused
+// with as long as nothing is attached to it
+const NAME_IN_USE: &str = "that name is already in use";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NamePolicy {
+    // `podman run` fails with "name is already in use"
+    #[default]
+    Fail,
+    // Stop and remove the existing container (`podman run --replace`)
+    Replace,
+    // Run as "<name>-<random suffix>" if the name is in use. Podman is left to detect the
+    // conflict, so that concurrent launches cannot pick the same name.
+    UniqueSuffix,
+}
+
+// A container as launched, under the name it was finally given
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamedContainer {
+    pub id: String,
+    pub name: String,
+}
+
+// Run `launch` (a create or a detached run, never anything attached to the container) under the
+// name of `c_ctx`, then under suffixed names as long as podman reports the name in use, if the
+// policy allows. Returns the output of the last attempt and the name it used.
+pub(crate) fn launch_named(
+    c_ctx: &ContainerCtx,
+    mut launch: impl FnMut(&ContainerCtx) -> io::Result<Output>,
+) -> io::Result<(Output, String)> {
+    if c_ctx.name_policy != NamePolicy::UniqueSuffix {
+        return Ok((launch(c_ctx)?, c_ctx.name.clone()));
+    }
+
+    let mut named = c_ctx.clone();
+    let mut attempts = 1;
+    loop {
+        let output = launch(&named)?;
+        if output.status.success() || !name_in_use(&output) || attempts == MAX_ATTEMPTS {
+            return Ok((output, named.name));
+        }
+        attempts += 1;
+        named.name = format!("{}-{}", c_ctx.name, random_suffix());
+    }
+}
+
+// Create the container under a free name, e.g. for launches not capturing the output of
+// podman, which cannot tell a name conflict from a failure of the container otherwise.
+// `stdin` keeps stdin open for a later `start --attach --interactive`, `keep` leaves out --rm.
+pub(crate) fn create_named<I, S>(
+    edf: &EDF,
+    p_ctx: Option<&PodmanCtx>,
+    c_ctx: &ContainerCtx,
+    container_cmd: I,
+    stdin: bool,
+    keep: bool,
+) -> anyhow::Result<NamedContainer>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let container_cmd = owned_args(container_cmd);
+    let (output, name) = launch_named(c_ctx, |c_ctx| {
+        let mut cmd = if keep {
+            commands::create_from_edf_kept(edf, p_ctx, c_ctx, &container_cmd)
+        } else {
+            commands::create_from_edf_stdin(edf, p_ctx, c_ctx, &container_cmd, stdin)
+        };
+        cmd.output()
+    })?;
+    let output = checked(output, "podman create")?;
+    let id = str::from_utf8(&output.stdout)?.trim().to_string();
+    Ok(NamedContainer { id, name })
+}
+
+// Launch through create and start for the policy to apply: the name is settled before the
+// container runs, so that its output is never taken for a conflict. Returns the exit status of
+// start and the name the container was given.
+pub(crate) fn run_named<I, S>(
+    edf: &EDF,
+    p_ctx: Option<&PodmanCtx>,
+    c_ctx: &ContainerCtx,
+    container_cmd: I,
+) -> anyhow::Result<(ExitStatus, String)>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let created = create_named(edf, p_ctx, c_ctx, container_cmd, false, false)?;
+    let status = start_named(&created.id, p_ctx, c_ctx).status()?;
+    Ok((status, created.name))
+}
+
+// Start of a container from create_named(), attached unless `ContainerCtx::detach` as for run.
+// Detached, start prints the ID as a detached run does.
+pub(crate) fn start_named(id: &str, p_ctx: Option<&PodmanCtx>, c_ctx: &ContainerCtx) -> Command {
+    commands::start(id, !c_ctx.detach, c_ctx.interactive && !c_ctx.detach, p_ctx)
+}
+
+fn name_in_use(output: &Output) -> bool {
+    output.status.code() == Some(ENGINE_ERROR_CODE)
+        && String::from_utf8_lossy(&output.stderr).contains(NAME_IN_USE)
+}
+
+// Randomly keyed, so that ranks retrying at the same time pick different names
+fn random_suffix() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let hash = RandomState::new().hash_one(now.as_nanos());
+    format!("{:06x}", hash & 0xff_ffff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{FakePodman, test_edf};
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_unique_suffix() -> anyhow::Result<()> {
+        // Leftover of a previous step: rank0
+        let podman = FakePodman::new(
+            "naming",
+            r#"case "$*" in
+    *"--name rank0 "*)
+        echo "Error: creating container storage: the container name \"rank0\" is already in use by aaaa1111. You have to remove that container to be able to reuse that name: that name is already in use" >&2
+        exit 125 ;;
+    create*) echo "bbbb2222" ;;
+    start*) echo "hello" ;;
+esac
+"#,
+        );
+        let p_ctx = podman.ctx();
+        let edf = test_edf("alpine.toml");
+
+        let mut c_ctx = ContainerCtx {
+            name: String::from("rank0"),
+            ..Default::default()
+        };
+        assert!(create_named(&edf, Some(&p_ctx), &c_ctx, ["true"], false, false).is_err());
+
+        c_ctx.name_policy = NamePolicy::UniqueSuffix;
+        let suffixed = create_named(&edf, Some(&p_ctx), &c_ctx, ["true"], false, false)?;
+        assert_eq!(suffixed.id, "bbbb2222");
+        assert!(suffixed.name.starts_with("rank0-"));
+        assert_eq!(suffixed.name.len(), "rank0-".len() + 6);
+
+        c_ctx.name = String::from("rank1");
+        let created = create_named(&edf, Some(&p_ctx), &c_ctx, ["true"], false, false)?;
+        assert_eq!(created.name, "rank1");
+
+        let calls = podman.calls();
+        assert_eq!(calls.len(), 4);
+        assert!(calls[1].contains(" --name rank0 "));
+        assert!(calls[2].contains(&format!(" --name {} ", suffixed.name)));
+        assert!(calls[3].contains(" --name rank1 "));
+
+        // Captured runs settle the name by create before anything runs
+        c_ctx.name = String::from("rank0");
+        let (output, name) = crate::run_from_edf_output(&edf, Some(&p_ctx), &c_ctx, ["true"]);
+        assert_eq!(output.stdout, b"hello\n");
+        assert!(name.starts_with("rank0-"));
+        let calls = podman.calls();
+        assert!(calls[4].starts_with("create ") && calls[4].contains(" --name rank0 "));
+        assert!(calls[5].starts_with("create ") && calls[5].contains(&format!(" --name {name} ")));
+        assert_eq!(calls[6], "start --attach bbbb2222");
+
+        let (executed, name) = crate::loggable::run_from_edf(&edf, Some(&p_ctx), &c_ctx, ["true"]);
+        assert!(name.starts_with("rank0-"));
+        assert!(executed.command.contains(" create ") && executed.command.contains(&name));
+        assert!(executed.command.ends_with("start --attach bbbb2222"));
+        assert_eq!(executed.output.stdout, b"hello\n");
+
+        // Only podman's own refusal of the name is retried, not a container failing the same way
+        let mut attempts = 0;
+        let (_, name) = launch_named(&c_ctx, |_| {
+            attempts += 1;
+            Ok(Output {
+                status: ExitStatus::from_raw(1 << 8),
+                stdout: Vec::new(),
+                stderr: b"bind: Address already in use".to_vec(),
+            })
+        })?;
+        assert_eq!((attempts, name.as_str()), (1, "rank0"));
+        let mut attempts = 0;
+        launch_named(&c_ctx, |_| {
+            attempts += 1;
+            Ok(Output {
+                status: ExitStatus::from_raw(125 << 8),
+                stdout: Vec::new(),
+                stderr: format!("Error: {NAME_IN_USE}").into_bytes(),
+            })
+        })?;
+        assert_eq!(attempts, MAX_ATTEMPTS);
+
+        // Replacing is left to podman
+        c_ctx.name_policy = NamePolicy::Replace;
+        let cmd = commands::run_from_edf(&edf, Some(&p_ctx), &c_ctx, ["true"]);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert!(args.contains(&"--replace".as_ref()));
+        Ok(())
+    }
+}
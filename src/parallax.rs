@@ -0,0 +1,630 @@
+use crate::{PodmanCtx, checked_output, commands, lock, storage};
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+// Concurrent runs on the same image of the store wait for each other
+fn parallax_execute_command(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    ro_store: &Path,
+    image: &str,
+    action: &str,
+) -> anyhow::Result<()> {
+    let _lock = lock::lock_image(ro_store, image, &podman_ctx.parallax_lock)?;
+    parallax_execute_unlocked(parallax_path, podman_ctx, ro_store, image, action)
+}
+
+fn parallax_execute_unlocked(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    ro_store: &Path,
+    image: &str,
+    action: &str,
+) -> anyhow::Result<()> {
+    let output = commands::parallax(parallax_path, podman_ctx, ro_store, image, action)?
+        .output()
+        .with_context(|| format!("Failed to execute `parallax {action}`"))?;
+
+    if !output.status.success() {
+        // include stderr to make debugging nicer
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("parallax {action} failed: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+fn default_ro_store(podman_ctx: &PodmanCtx) -> anyhow::Result<&Path> {
+    podman_ctx
+        .ro_store()
+        .ok_or_else(|| anyhow::anyhow!("Missing read-only store path in PodmanCtx"))
+}
+
+pub(crate) fn graphroot(podman_ctx: &PodmanCtx) -> anyhow::Result<&Path> {
+    podman_ctx
+        .graphroot
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("Missing graphroot in PodmanCtx"))
+}
+
+// Migrate into the first read-only store of the context
+pub fn parallax_migrate(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    image: &str,
+) -> anyhow::Result<()> {
+    let ro_store = default_ro_store(podman_ctx)?;
+    parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "migrate")
+}
+
+pub fn parallax_migrate_to(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    ro_store: &Path,
+    image: &str,
+) -> anyhow::Result<()> {
+    parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "migrate")
+}
+
+// Remove from the first read-only store of the context
+pub fn parallax_rmi(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    image: &str,
+) -> anyhow::Result<()> {
+    let ro_store = default_ro_store(podman_ctx)?;
+    parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "rmi")
+}
+
+pub fn parallax_rmi_from(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    ro_store: &Path,
+    image: &str,
+) -> anyhow::Result<()> {
+    parallax_execute_command(parallax_path, podman_ctx, ro_store, image, "rmi")
+}
+
+// Image as recorded in a read-only store populated by parallax
+#[derive(Clone, Debug)]
+pub struct RoStoreImage {
+    pub id: String,
+    pub names: Vec<String>,
+    pub digest: Option<String>,
+    pub ro_store: PathBuf,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MigrationStatus {
+    // Neither in graphroot nor in any read-only store of the context
+    Absent,
+    // Only in graphroot, `parallax_migrate` did not run or did not succeed
+    NotMigrated,
+    // Migrated into `ro_store`, the first store of the context holding the image.
+    // `in_graphroot` tells whether the graphroot copy is still around.
+    Migrated {
+        ro_store: PathBuf,
+        in_graphroot: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PullPolicy {
+    // Only use images already in a store, an image left in graphroot is still migrated
+    Never,
+    // Pull when no store holds the image
+    #[default]
+    Missing,
+    // Always pull, migrating again only if the registry served a different image
+    Always,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnsureStep {
+    Pulled,
+    Migrated,
+    RemovedFromGraphroot,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnsureReport {
+    // Read-only store podman resolves the image from
+    pub ro_store: PathBuf,
+    // Steps performed, in order. Empty if the image was already migrated.
+    pub steps: Vec<EnsureStep>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParallaxCheck {
+    pub image: String,
+    pub ro_store: PathBuf,
+    pub passed: bool,
+    // Non-empty output lines of `parallax --check`, stdout first
+    pub messages: Vec<String>,
+}
+
+// List the images of a single read-only store
+pub fn parallax_list_store(ro_store: &Path) -> anyhow::Result<Vec<RoStoreImage>> {
+    let images = storage::read_images(ro_store)?
+        .into_iter()
+        .map(|img| RoStoreImage {
+            id: img.id,
+            names: img.names,
+            digest: img.digest,
+            ro_store: ro_store.to_path_buf(),
+        })
+        .collect();
+    Ok(images)
+}
+
+// List the images of every read-only store of the context, in lookup order
+pub fn parallax_list(podman_ctx: &PodmanCtx) -> anyhow::Result<Vec<RoStoreImage>> {
+    let mut images = Vec::new();
+    for ro_store in &podman_ctx.ro_stores {
+        images.extend(parallax_list_store(ro_store)?);
+    }
+    Ok(images)
+}
+
+pub fn parallax_status(podman_ctx: &PodmanCtx, image: &str) -> anyhow::Result<MigrationStatus> {
+    let graphroot = graphroot(podman_ctx)?;
+    let in_graphroot = storage::find_image(graphroot, image)?.is_some();
+
+    for ro_store in &podman_ctx.ro_stores {
+        if storage::find_image(ro_store, image)?.is_some() {
+            return Ok(MigrationStatus::Migrated {
+                ro_store: ro_store.clone(),
+                in_graphroot,
+            });
+        }
+    }
+
+    if in_graphroot {
+        Ok(MigrationStatus::NotMigrated)
+    } else {
+        Ok(MigrationStatus::Absent)
+    }
+}
+
+// Verify the squashfs image of `image` in the first read-only store of the context
+pub fn parallax_check(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    image: &str,
+) -> anyhow::Result<ParallaxCheck> {
+    let ro_store = default_ro_store(podman_ctx)?;
+    parallax_check_in(parallax_path, podman_ctx, ro_store, image)
+}
+
+// A failed verification is reported through `ParallaxCheck::passed`, errors are reserved to
+// images missing from the store and parallax failing to run. Like migrations, the check waits
+// for concurrent runs on the same image, unless the store cannot be written to.
+pub fn parallax_check_in(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    ro_store: &Path,
+    image: &str,
+) -> anyhow::Result<ParallaxCheck> {
+    let _lock = lock::lock_image_if_writable(ro_store, image, &podman_ctx.parallax_lock)?;
+    if storage::find_image(ro_store, image)?.is_none() {
+        anyhow::bail!("image {image} not found in {}", ro_store.display());
+    }
+
+    let output = commands::parallax(parallax_path, podman_ctx, ro_store, image, "check")?
+        .output()
+        .with_context(|| format!("cannot run {}", parallax_path.display()))?;
+
+    let mut messages = Vec::new();
+    for out in [&output.stdout, &output.stderr] {
+        let out = String::from_utf8_lossy(out);
+        messages.extend(
+            out.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(String::from),
+        );
+    }
+
+    Ok(ParallaxCheck {
+        image: image.to_string(),
+        ro_store: ro_store.to_path_buf(),
+        passed: output.status.success(),
+        messages,
+    })
+}
+
+// Make `image` available from a read-only store: pull it into graphroot if needed, migrate it
+// into the first read-only store of the context and drop the graphroot copy.
+// The image is locked in the first store for the whole sequence, so that concurrent callers
+// find it migrated instead of migrating it again. An image already migrated needs no lock, nor
+// write access to the stores.
+pub fn ensure_image(
+    parallax_path: &PathBuf,
+    podman_ctx: &PodmanCtx,
+    image: &str,
+    policy: PullPolicy,
+) -> anyhow::Result<EnsureReport> {
+    let graphroot = graphroot(podman_ctx)?;
+    let default_store = default_ro_store(podman_ctx)?;
+    match parallax_status(podman_ctx, image)? {
+        MigrationStatus::Migrated {
+            ro_store,
+            in_graphroot: false,
+        } if policy != PullPolicy::Always => {
+            return Ok(EnsureReport {
+                ro_store,
+                steps: Vec::new(),
+            });
+        }
+        _ => {}
+    }
+
+    // Checked again under the lock, a concurrent caller may have been migrating the image
+    let _lock = lock::lock_image(default_store, image, &podman_ctx.parallax_lock)?;
+    let mut steps = Vec::new();
+
+    let (migrated, in_graphroot) = match parallax_status(podman_ctx, image)? {
+        MigrationStatus::Migrated {
+            ro_store,
+            in_graphroot,
+        } => (Some(ro_store), in_graphroot),
+        MigrationStatus::NotMigrated => (None, true),
+        MigrationStatus::Absent => (None, false),
+    };
+    let available = migrated.is_some() || in_graphroot;
+
+    match policy {
+        PullPolicy::Never if !available => {
+            anyhow::bail!("image {image} is not available and the pull policy forbids pulling")
+        }
+        PullPolicy::Always => pull(podman_ctx, image, &mut steps)?,
+        PullPolicy::Missing if !available => pull(podman_ctx, image, &mut steps)?,
+        _ => {}
+    }
+
+    let Some(local) = storage::find_image(graphroot, image)? else {
+        let ro_store = migrated
+            .ok_or_else(|| anyhow::anyhow!("image {image} not found in {}", graphroot.display()))?;
+        return Ok(EnsureReport { ro_store, steps });
+    };
+
+    // A store may already hold this very image, e.g. when refreshing an unchanged tag. A
+    // different graphroot copy is newer than the migrated one, since migrating drops the
+    // graphroot copy, and is migrated again.
+    let ro_store = match store_holding(podman_ctx, &local)? {
+        Some(store) => store,
+        None => {
+            parallax_execute_unlocked(parallax_path, podman_ctx, default_store, image, "migrate")?;
+            steps.push(EnsureStep::Migrated);
+            default_store.to_path_buf()
+        }
+    };
+
+    remove_from_graphroot(podman_ctx, &local.id)?;
+    steps.push(EnsureStep::RemovedFromGraphroot);
+
+    Ok(EnsureReport { ro_store, steps })
+}
+
+fn pull(podman_ctx: &PodmanCtx, image: &str, steps: &mut Vec<EnsureStep>) -> anyhow::Result<()> {
+    checked_output(&mut commands::pull(image, Some(podman_ctx)), "podman pull")?;
+    steps.push(EnsureStep::Pulled);
+    Ok(())
+}
+
+// First read-only store holding `image`, the same ID or manifest digest
+fn store_holding(
+    podman_ctx: &PodmanCtx,
+    image: &storage::StoredImage,
+) -> anyhow::Result<Option<PathBuf>> {
+    for store in &podman_ctx.ro_stores {
+        let same = storage::read_images(store)?
+            .iter()
+            .any(|img| img.id == image.id || (img.digest.is_some() && img.digest == image.digest));
+        if same {
+            return Ok(Some(store.clone()));
+        }
+    }
+    Ok(None)
+}
+
+// Remove by ID: a name may also resolve to the copy in a read-only store
+fn remove_from_graphroot(podman_ctx: &PodmanCtx, image_id: &str) -> anyhow::Result<()> {
+    checked_output(&mut commands::rmi(image_id, Some(podman_ctx)), "podman rmi")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LockConfig;
+    use crate::testutil::{TestDir, podman_ctx, write_script};
+    use std::fs;
+    use std::thread;
+
+    fn write_images_json(store: &Path, json: &str) {
+        fs::create_dir_all(store.join("overlay-images")).unwrap();
+        fs::write(store.join("overlay-images/images.json"), json).unwrap();
+    }
+
+    const ALPINE: &str =
+        r#"[{"id":"aaaa1111","names":["docker.io/library/alpine:3.22"],"digest":"sha256:aaaa"}]"#;
+    const ALPINE_UPDATED: &str =
+        r#"[{"id":"bbbb2222","names":["docker.io/library/alpine:3.22"],"digest":"sha256:bbbb"}]"#;
+
+    // Fake podman and parallax which only maintain images.json and log their invocations.
+    // Pulls get the images.json in `root/registry`.
+    fn fake_tools(root: &TestDir) -> (PathBuf, PathBuf, PathBuf) {
+        let log = root.join("calls.log");
+        let podman = root.join("podman");
+        let parallax = root.join("parallax");
+        let registry = root.join("registry");
+        fs::write(&registry, ALPINE).unwrap();
+
+        write_script(
+            &podman,
+            &format!(
+                r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --root) root="$2"; shift 2 ;;
+        --runroot|--module|--storage-opt) shift 2 ;;
+        pull)
+            echo "pull $2" >> {log}
+            mkdir -p "$root/overlay-images"
+            cp {registry} "$root/overlay-images/images.json"
+            exit 0 ;;
+        rmi)
+            echo "rmi $2" >> {log}
+            echo '[]' > "$root/overlay-images/images.json"
+            exit 0 ;;
+        *) exit 125 ;;
+    esac
+done
+"#,
+                log = log.display(),
+                registry = registry.display()
+            ),
+        );
+        // parallax --podmanRoot <graphroot> --roStoragePath <store> --migrate --image <image>
+        write_script(
+            &parallax,
+            &format!(
+                r#"#!/bin/sh
+echo "$5 $7" >> {log}
+mkdir -p "$4/overlay-images"
+cp "$2/overlay-images/images.json" "$4/overlay-images/images.json"
+"#,
+                log = log.display()
+            ),
+        );
+
+        (podman, parallax, log)
+    }
+
+    #[test]
+    fn test_ensure_image() -> anyhow::Result<()> {
+        let root = TestDir::new("ensure");
+        let (podman, parallax, log) = fake_tools(&root);
+        let site_store = root.join("site");
+
+        let p_ctx = PodmanCtx {
+            graphroot: Some(root.join("graphroot")),
+            ro_stores: vec![site_store.clone()],
+            ..podman_ctx(podman)
+        };
+
+        let err = ensure_image(&parallax, &p_ctx, "alpine:3.22", PullPolicy::Never).unwrap_err();
+        assert!(err.to_string().contains("forbids pulling"));
+
+        let report = ensure_image(&parallax, &p_ctx, "alpine:3.22", PullPolicy::Missing)?;
+        assert_eq!(report.ro_store, site_store);
+        assert_eq!(
+            report.steps,
+            [
+                EnsureStep::Pulled,
+                EnsureStep::Migrated,
+                EnsureStep::RemovedFromGraphroot
+            ]
+        );
+
+        let report = ensure_image(&parallax, &p_ctx, "alpine:3.22", PullPolicy::Missing)?;
+        assert!(report.steps.is_empty());
+
+        // Nothing to do is done without locking, e.g. in a store users cannot write to
+        let locks = site_store.join(".parallax-locks");
+        fs::rename(&locks, root.join("locks"))?;
+        fs::write(&locks, "")?;
+        let report = ensure_image(&parallax, &p_ctx, "alpine:3.22", PullPolicy::Never)?;
+        assert_eq!(
+            (report.ro_store, report.steps),
+            (site_store.clone(), Vec::new())
+        );
+        fs::remove_file(&locks)?;
+
+        // Same image served again by the registry: no need to migrate it twice
+        let report = ensure_image(&parallax, &p_ctx, "alpine:3.22", PullPolicy::Always)?;
+        assert_eq!(
+            report.steps,
+            [EnsureStep::Pulled, EnsureStep::RemovedFromGraphroot]
+        );
+
+        // The registry serves an update, which replaces the migrated image
+        fs::write(root.join("registry"), ALPINE_UPDATED)?;
+        let report = ensure_image(&parallax, &p_ctx, "alpine:3.22", PullPolicy::Always)?;
+        assert_eq!(
+            report.steps,
+            [
+                EnsureStep::Pulled,
+                EnsureStep::Migrated,
+                EnsureStep::RemovedFromGraphroot
+            ]
+        );
+        assert_eq!(parallax_list_store(&site_store)?[0].id, "bbbb2222");
+
+        // An update pulled into graphroot by hand is migrated rather than dropped
+        write_images_json(
+            &root.join("graphroot"),
+            r#"[{"id":"cccc3333","names":["docker.io/library/alpine:3.22"],"digest":"sha256:cccc"}]"#,
+        );
+        let report = ensure_image(&parallax, &p_ctx, "alpine:3.22", PullPolicy::Missing)?;
+        assert_eq!(
+            report.steps,
+            [EnsureStep::Migrated, EnsureStep::RemovedFromGraphroot]
+        );
+
+        let calls = fs::read_to_string(&log)?;
+        let calls: Vec<&str> = calls.lines().collect();
+        assert_eq!(
+            calls,
+            [
+                "pull alpine:3.22",
+                "--migrate alpine:3.22",
+                "rmi aaaa1111",
+                "pull alpine:3.22",
+                "rmi aaaa1111",
+                "pull alpine:3.22",
+                "--migrate alpine:3.22",
+                "rmi bbbb2222",
+                "--migrate alpine:3.22",
+                "rmi cccc3333",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallax_list_and_status() -> anyhow::Result<()> {
+        let root = TestDir::new("parallax");
+        let graphroot = root.join("graphroot");
+        let site_store = root.join("site");
+        let project_store = root.join("project");
+
+        write_images_json(
+            &graphroot,
+            r#"[{"id":"aaaa1111","names":["docker.io/library/alpine:3.22"]},
+                {"id":"bbbb2222","names":["docker.io/library/ubuntu:24.04"]}]"#,
+        );
+        write_images_json(
+            &project_store,
+            r#"[{"id":"aaaa1111","names":["docker.io/library/alpine:3.22"],"digest":null},
+                {"id":"cccc3333","names":null}]"#,
+        );
+        // The site store exists but nothing was migrated there yet
+
+        let p_ctx = PodmanCtx {
+            graphroot: Some(graphroot),
+            ro_stores: vec![site_store.clone(), project_store.clone()],
+            ..podman_ctx(PathBuf::from("/usr/bin/podman"))
+        };
+
+        assert!(parallax_list_store(&site_store)?.is_empty());
+        let listed = parallax_list(&p_ctx)?;
+        let ids: Vec<&str> = listed.iter().map(|img| img.id.as_str()).collect();
+        assert_eq!(ids, ["aaaa1111", "cccc3333"]);
+        assert!(listed.iter().all(|img| img.ro_store == project_store));
+
+        assert_eq!(
+            parallax_status(&p_ctx, "alpine:3.22")?,
+            MigrationStatus::Migrated {
+                ro_store: project_store,
+                in_graphroot: true
+            }
+        );
+        assert_eq!(
+            parallax_status(&p_ctx, "ubuntu:24.04")?,
+            MigrationStatus::NotMigrated
+        );
+        assert_eq!(
+            parallax_status(&p_ctx, "rockylinux:9")?,
+            MigrationStatus::Absent
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallax_check() -> anyhow::Result<()> {
+        let root = TestDir::new("check");
+        let store = root.join("store");
+        let parallax = root.join("parallax");
+        write_images_json(
+            &store,
+            r#"[{"id":"aaaa1111","names":["docker.io/library/alpine:3.22"]}]"#,
+        );
+        // Only passes if the image was locked
+        write_script(
+            &parallax,
+            r#"#!/bin/sh
+ls "$4/.parallax-locks" | grep -q . || exit 3
+echo "squashfs checksum mismatch" >&2
+exit 1
+"#,
+        );
+
+        let p_ctx = PodmanCtx {
+            graphroot: Some(root.join("graphroot")),
+            ro_stores: vec![store.clone()],
+            ..podman_ctx(PathBuf::from("/usr/bin/podman"))
+        };
+
+        let check = parallax_check(&parallax, &p_ctx, "alpine:3.22")?;
+        assert!(!check.passed);
+        assert_eq!(check.messages, ["squashfs checksum mismatch"]);
+
+        assert!(parallax_check(&parallax, &p_ctx, "ubuntu:24.04").is_err());
+        assert!(parallax_check(&root.join("missing"), &p_ctx, "alpine:3.22").is_err());
+        // Errors rather than panics
+        assert!(parallax_migrate(&root.join("missing"), &p_ctx, "alpine:3.22").is_err());
+        let no_graphroot = PodmanCtx {
+            ro_stores: vec![store.clone()],
+            ..podman_ctx(PathBuf::from("/usr/bin/podman"))
+        };
+        let err = parallax_check(&parallax, &no_graphroot, "alpine:3.22").unwrap_err();
+        assert_eq!(err.to_string(), "Missing graphroot in PodmanCtx");
+        assert!(parallax_migrate(&parallax, &no_graphroot, "alpine:3.22").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_migrations_are_serialized() -> anyhow::Result<()> {
+        let root = TestDir::new("lock");
+        let store = root.join("store");
+        let log = root.join("calls.log");
+        let parallax = root.join("parallax");
+        fs::create_dir_all(&store)?;
+
+        // mkdir is atomic: it fails if another migration is in flight
+        write_script(
+            &parallax,
+            &format!(
+                r#"#!/bin/sh
+mkdir "$4/inflight" 2>/dev/null || echo overlap >> {log}
+sleep 0.2
+rmdir "$4/inflight"
+echo done >> {log}
+"#,
+                log = log.display()
+            ),
+        );
+
+        let p_ctx = PodmanCtx {
+            graphroot: Some(root.join("graphroot")),
+            ro_stores: vec![store],
+            parallax_lock: LockConfig {
+                poll_interval: std::time::Duration::from_millis(20),
+                ..LockConfig::default()
+            },
+            ..podman_ctx(PathBuf::from("/usr/bin/podman"))
+        };
+
+        thread::scope(|scope| {
+            for image in ["alpine:3.22", "docker.io/library/alpine:3.22"].repeat(2) {
+                let (p_ctx, parallax) = (&p_ctx, &parallax);
+                scope.spawn(move || parallax_migrate(parallax, p_ctx, image).unwrap());
+            }
+        });
+
+        let calls = fs::read_to_string(&log)?;
+        assert_eq!(calls.lines().collect::<Vec<_>>(), ["done"; 4]);
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+// Host-side view of a container process, read from /proc and the cgroup v2 hierarchy.
+// Only the PID is needed, e.g. from get_container_pid_from_storage().
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Clone, Debug)]
+pub struct ContainerProcessInfo {
+    pub pid: u32,
+    // Namespace inode by type ("mnt", "net", "pid", ...). Two processes share a namespace if
+    // they have the same inode for it.
+    pub namespaces: BTreeMap<String, u64>,
+    // Cgroup v2 path, relative to the root of the hierarchy
+    pub cgroup: PathBuf,
+    pub stats: CgroupStats,
+}
+
+impl ContainerProcessInfo {
+    // Path to join a namespace of the process, e.g. with setns(2) or `nsenter`
+    pub fn ns_path(&self, kind: &str) -> PathBuf {
+        PathBuf::from(format!("/proc/{}/ns/{kind}", self.pid))
+    }
+
+    pub fn cgroup_dir(&self) -> PathBuf {
+        Path::new(CGROUP_ROOT).join(self.cgroup.strip_prefix("/").unwrap_or(&self.cgroup))
+    }
+
+    pub fn refresh_stats(&mut self) -> anyhow::Result<()> {
+        self.stats = CgroupStats::read(&self.cgroup_dir())?;
+        Ok(())
+    }
+}
+
+// Snapshot of the cgroup counters. A counter is None when its controller is not enabled for
+// the cgroup or the kernel is too old to provide it (e.g. memory.peak before Linux 5.19).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CgroupStats {
+    pub cpu_usage_usec: Option<u64>,
+    pub cpu_user_usec: Option<u64>,
+    pub cpu_system_usec: Option<u64>,
+    pub memory_current: Option<u64>,
+    pub memory_peak: Option<u64>,
+    // None also when unlimited
+    pub memory_max: Option<u64>,
+    pub oom_kills: Option<u64>,
+    pub pids_current: Option<u64>,
+    pub pids_peak: Option<u64>,
+    // None also when unlimited
+    pub pids_max: Option<u64>,
+}
+
+impl CgroupStats {
+    pub fn read(cgroup_dir: &Path) -> anyhow::Result<Self> {
+        let cpu = read_keyed(&cgroup_dir.join("cpu.stat"))?;
+        let memory_events = read_keyed(&cgroup_dir.join("memory.events"))?;
+
+        Ok(Self {
+            cpu_usage_usec: cpu.get("usage_usec").copied(),
+            cpu_user_usec: cpu.get("user_usec").copied(),
+            cpu_system_usec: cpu.get("system_usec").copied(),
+            memory_current: read_value(&cgroup_dir.join("memory.current"))?,
+            memory_peak: read_value(&cgroup_dir.join("memory.peak"))?,
+            memory_max: read_value(&cgroup_dir.join("memory.max"))?,
+            oom_kills: memory_events.get("oom_kill").copied(),
+            pids_current: read_value(&cgroup_dir.join("pids.current"))?,
+            pids_peak: read_value(&cgroup_dir.join("pids.peak"))?,
+            pids_max: read_value(&cgroup_dir.join("pids.max"))?,
+        })
+    }
+}
+
+pub fn container_process_info(pid: u32) -> anyhow::Result<ContainerProcessInfo> {
+    let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+
+    let mut namespaces = BTreeMap::new();
+    let ns_dir = proc_dir.join("ns");
+    for entry in
+        fs::read_dir(&ns_dir).with_context(|| format!("cannot read {}", ns_dir.display()))?
+    {
+        let entry = entry?;
+        let link = fs::read_link(entry.path())?;
+        // e.g. "net:[4026531840]"
+        let inode = link
+            .to_str()
+            .and_then(|l| l.split_once(":["))
+            .and_then(|(_, ino)| ino.strip_suffix(']'))
+            .and_then(|ino| ino.parse().ok());
+        if let Some(inode) = inode {
+            namespaces.insert(entry.file_name().to_string_lossy().into_owned(), inode);
+        }
+    }
+
+    let cgroup = fs::read_to_string(proc_dir.join("cgroup"))?;
+    let cgroup = parse_cgroup_v2(&cgroup)
+        .ok_or_else(|| anyhow::anyhow!("process {pid} is not in a cgroup v2 hierarchy"))?;
+
+    let mut info = ContainerProcessInfo {
+        pid,
+        namespaces,
+        cgroup,
+        stats: CgroupStats::default(),
+    };
+    info.refresh_stats()?;
+    Ok(info)
+}
+
+// The unified hierarchy is the "0::<path>" entry of /proc/<pid>/cgroup
+fn parse_cgroup_v2(proc_cgroup: &str) -> Option<PathBuf> {
+    proc_cgroup
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .map(PathBuf::from)
+}
+
+// Single value files. "max" stands for no limit.
+fn read_value(path: &Path) -> anyhow::Result<Option<u64>> {
+    match fs::read_to_string(path) {
+        Ok(val) => match val.trim() {
+            "max" => Ok(None),
+            val => Ok(Some(val.parse().with_context(|| {
+                format!("invalid value {val:?} in {}", path.display())
+            })?)),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("cannot read {}", path.display())),
+    }
+}
+
+// Flat keyed files, i.e. "<key> <value>" lines
+fn read_keyed(path: &Path) -> anyhow::Result<BTreeMap<String, u64>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+
+    Ok(content
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .filter_map(|(k, v)| Some((k.to_string(), v.trim().parse().ok()?)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestDir;
+
+    #[test]
+    fn test_cgroup_stats() -> anyhow::Result<()> {
+        let dir = TestDir::new("cgroup");
+        fs::write(
+            dir.join("cpu.stat"),
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\nnr_periods 0\n",
+        )?;
+        fs::write(dir.join("memory.current"), "4096\n")?;
+        fs::write(dir.join("memory.max"), "max\n")?;
+        fs::write(
+            dir.join("memory.events"),
+            "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n",
+        )?;
+        fs::write(dir.join("pids.current"), "7\n")?;
+        fs::write(dir.join("pids.max"), "1024\n")?;
+
+        let stats = CgroupStats::read(dir.path())?;
+        assert_eq!(
+            stats,
+            CgroupStats {
+                cpu_usage_usec: Some(1500),
+                cpu_user_usec: Some(1000),
+                cpu_system_usec: Some(500),
+                memory_current: Some(4096),
+                memory_peak: None,
+                memory_max: None,
+                oom_kills: Some(1),
+                pids_current: Some(7),
+                pids_peak: None,
+                pids_max: Some(1024),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cgroup() {
+        let v2 = "0::/user.slice/user-1000.slice/libpod-4f0c3b1e.scope/container\n";
+        assert_eq!(
+            parse_cgroup_v2(v2),
+            Some(PathBuf::from(
+                "/user.slice/user-1000.slice/libpod-4f0c3b1e.scope/container"
+            ))
+        );
+
+        let v1 = "12:pids:/user.slice\n11:memory:/user.slice\n";
+        assert_eq!(parse_cgroup_v2(v1), None);
+    }
+
+    #[test]
+    fn test_own_namespaces() -> anyhow::Result<()> {
+        // The cgroup of the test process may not be v2, only check namespaces
+        let ns_dir = format!("/proc/{}/ns", std::process::id());
+        let ns_count = fs::read_dir(ns_dir)?.count();
+
+        match container_process_info(std::process::id()) {
+            Ok(info) => {
+                assert_eq!(info.namespaces.len(), ns_count);
+                let mnt = fs::read_link(info.ns_path("mnt"))?;
+                assert_eq!(
+                    mnt,
+                    PathBuf::from(format!("mnt:[{}]", info.namespaces["mnt"]))
+                );
+            }
+            Err(e) => assert!(e.to_string().contains("cgroup v2"), "{e}"),
+        }
+        Ok(())
+    }
+}
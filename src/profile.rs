@@ -0,0 +1,117 @@
+// Namespace and runtime settings of a container, rendered into `podman run` flags.
+// Unset settings keep the podman defaults.
+use crate::ResourceLimits;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeProfile {
+    pub network: Option<NamespaceMode>,
+    pub ipc: Option<NamespaceMode>,
+    pub pid: Option<NamespaceMode>,
+    pub cgroups: Option<CgroupsMode>,
+    pub cgroupns: Option<NamespaceMode>,
+    // Pass all the ulimits of the caller (e.g. those set by Slurm) to the container
+    pub host_ulimits: bool,
+}
+
+impl RuntimeProfile {
+    // Host network, IPC and PID namespaces for MPI and RDMA, and no cgroups of its own: the job
+    // step's cgroup already confines the container, and conmon is not charged to it. Resource
+    // limits need cgroups, so they are rejected with this profile.
+    pub fn hpc() -> Self {
+        Self {
+            network: Some(NamespaceMode::Host),
+            ipc: Some(NamespaceMode::Host),
+            pid: Some(NamespaceMode::Host),
+            cgroups: Some(CgroupsMode::Disabled),
+            cgroupns: Some(NamespaceMode::Host),
+            host_ulimits: true,
+        }
+    }
+
+    // A namespace and cgroup of its own for everything
+    pub fn isolated() -> Self {
+        Self {
+            network: Some(NamespaceMode::Private),
+            ipc: Some(NamespaceMode::Private),
+            pid: Some(NamespaceMode::Private),
+            cgroups: Some(CgroupsMode::Enabled),
+            cgroupns: Some(NamespaceMode::Private),
+            host_ulimits: false,
+        }
+    }
+}
+
+impl RuntimeProfile {
+    // Reject limits the profile cannot honour, rather than having podman ignore them
+    pub(crate) fn check(&self, resources: &ResourceLimits) -> anyhow::Result<()> {
+        if self.cgroups == Some(CgroupsMode::Disabled) {
+            let limits = [
+                ("cpus", resources.cpus.is_some()),
+                ("memory", resources.memory.is_some()),
+                ("pids limit", resources.pids_limit.is_some()),
+                ("cpuset", resources.cpuset_cpus.is_some()),
+            ];
+            let set: Vec<&str> = limits
+                .into_iter()
+                .filter_map(|(name, set)| set.then_some(name))
+                .collect();
+            if !set.is_empty() {
+                anyhow::bail!(
+                    "resource limits ({}) cannot be enforced with cgroups disabled",
+                    set.join(", ")
+                );
+            }
+        }
+        if self.host_ulimits && !resources.ulimits.is_empty() {
+            anyhow::bail!("explicit ulimits conflict with passing the host ulimits");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NamespaceMode {
+    Host,
+    Private,
+    // No namespace content at all, e.g. only loopback for the network.
+    // Valid for --network and --ipc only.
+    Empty,
+    // Share the namespace of another container
+    Container(String),
+    // Join an existing namespace, e.g. /proc/<pid>/ns/net
+    Path(PathBuf),
+}
+
+impl NamespaceMode {
+    pub fn to_arg(&self) -> String {
+        match self {
+            Self::Host => String::from("host"),
+            Self::Private => String::from("private"),
+            Self::Empty => String::from("none"),
+            Self::Container(name) => format!("container:{name}"),
+            Self::Path(path) => format!("ns:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgroupsMode {
+    Enabled,
+    Disabled,
+    // A cgroup for the container, but conmon stays in the caller's
+    NoConmon,
+    // The container in a sub-cgroup of the caller's
+    Split,
+}
+
+impl CgroupsMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Enabled => "enabled",
+            Self::Disabled => "disabled",
+            Self::NoConmon => "no-conmon",
+            Self::Split => "split",
+        }
+    }
+}
@@ -0,0 +1,273 @@
+// Stdio of the podman process to and from files, e.g. per rank output of batch jobs, without
+// buffering it in memory as the *_output() functions do
+use crate::naming;
+use crate::{ContainerCtx, NamePolicy, PodmanCtx, checked_output, commands};
+use anyhow::Context;
+use raster::EDF;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+
+// Unset streams are inherited
+#[derive(Clone, Debug, Default)]
+pub struct Redirect {
+    pub stdin: Option<PathBuf>,
+    pub stdout: Option<OutputFile>,
+    pub stderr: Option<OutputFile>,
+    // Send stderr to the stdout file, interleaved as written. Exclusive with `stderr`.
+    pub merge_stderr: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    // Otherwise truncated
+    pub append: bool,
+}
+
+impl OutputFile {
+    pub fn truncate(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            append: false,
+        }
+    }
+
+    pub fn append(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            append: true,
+        }
+    }
+
+    fn open(&self) -> anyhow::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(self.append)
+            .truncate(!self.append)
+            .open(&self.path)
+            .with_context(|| format!("cannot open {}", self.path.display()))
+    }
+}
+
+// Files of a Redirect, opened and checked before anything is launched
+struct OpenedRedirect {
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+}
+
+impl OpenedRedirect {
+    fn apply(self, cmd: &mut Command) {
+        if let Some(stdin) = self.stdin {
+            cmd.stdin(stdin);
+        }
+        if let Some(stdout) = self.stdout {
+            cmd.stdout(stdout);
+        }
+        if let Some(stderr) = self.stderr {
+            cmd.stderr(stderr);
+        }
+    }
+}
+
+impl Redirect {
+    pub fn apply(&self, cmd: &mut Command) -> anyhow::Result<()> {
+        self.open()?.apply(cmd);
+        Ok(())
+    }
+
+    fn open(&self) -> anyhow::Result<OpenedRedirect> {
+        anyhow::ensure!(
+            !(self.merge_stderr && self.stderr.is_some()),
+            "stderr cannot go both to its own file and to the stdout file"
+        );
+        anyhow::ensure!(
+            !self.merge_stderr || self.stdout.is_some(),
+            "merging stderr requires a stdout file"
+        );
+
+        let stdin = self
+            .stdin
+            .as_ref()
+            .map(|stdin| {
+                File::open(stdin).with_context(|| format!("cannot open {}", stdin.display()))
+            })
+            .transpose()?;
+        let stdout = self.stdout.as_ref().map(OutputFile::open).transpose()?;
+        let stderr = match (&stdout, &self.stderr) {
+            (Some(stdout), _) if self.merge_stderr => Some(stdout.try_clone()?),
+            (_, Some(stderr)) => Some(stderr.open()?),
+            _ => None,
+        };
+        Ok(OpenedRedirect {
+            stdin,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+// `args` as for run(), which need --interactive for the container to read a redirected stdin
+pub fn run_redirected<I, S>(
+    args: I,
+    redirect: &Redirect,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<ExitStatus>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = commands::run(podman_ctx);
+    cmd.args(args);
+    redirect.apply(&mut cmd)?;
+    Ok(cmd.status()?)
+}
+
+pub fn run_from_edf_redirected<I, S>(
+    edf: &EDF,
+    p_ctx: Option<&PodmanCtx>,
+    c_ctx: &ContainerCtx,
+    container_cmd: I,
+    redirect: &Redirect,
+) -> anyhow::Result<ExitStatus>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    c_ctx.validate()?;
+    // Before creating, not to leave a container behind when a file cannot be opened
+    let opened = redirect.open()?;
+    let stdin = redirect.stdin.is_some();
+    // The output is redirected, so name conflicts are settled by creating first
+    let mut cmd = if c_ctx.name_policy == NamePolicy::UniqueSuffix {
+        let created = naming::create_named(edf, p_ctx, c_ctx, container_cmd, stdin, false)?;
+        commands::start(&created.id, !c_ctx.detach, stdin, p_ctx)
+    } else {
+        commands::run_from_edf_stdin(edf, p_ctx, c_ctx, container_cmd, stdin)
+    };
+    opened.apply(&mut cmd);
+    Ok(cmd.status()?)
+}
+
+// A redirected stdin needs the container created with stdin open, see create_from_edf_stdin()
+pub fn start_attached_redirected(
+    name: &str,
+    redirect: &Redirect,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<ExitStatus> {
+    if redirect.stdin.is_some() {
+        let output = checked_output(
+            &mut commands::inspect(name, Some("{{.Config.OpenStdin}}"), podman_ctx),
+            "podman inspect",
+        )?;
+        anyhow::ensure!(
+            str::from_utf8(&output.stdout)?.trim() == "true",
+            "container {name} was created with stdin closed and cannot read it"
+        );
+    }
+    let mut cmd = commands::start(name, true, redirect.stdin.is_some(), podman_ctx);
+    redirect.apply(&mut cmd)?;
+    Ok(cmd.status()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{FakePodman, test_edf};
+    use std::fs;
+
+    #[test]
+    fn test_run_redirected() -> anyhow::Result<()> {
+        // Echoes stdin to stdout, then reports on stderr. Only rank1 has stdin open.
+        let podman = FakePodman::new(
+            "redirect",
+            r#"case "$*" in
+    *OpenStdin*rank1) echo true; exit ;;
+    *OpenStdin*) echo false; exit ;;
+esac
+cat
+echo "done: $*" >&2
+"#,
+        );
+        let p_ctx = podman.ctx();
+        let root = podman.dir();
+        fs::write(root.join("input"), "rank 0 input\n")?;
+
+        let separate = Redirect {
+            stdin: Some(root.join("input")),
+            stdout: Some(OutputFile::truncate(root.join("rank0.out"))),
+            stderr: Some(OutputFile::append(root.join("rank0.err"))),
+            merge_stderr: false,
+        };
+        for _ in 0..2 {
+            assert!(run_redirected(["alpine"], &separate, Some(&p_ctx))?.success());
+        }
+        assert_eq!(
+            fs::read_to_string(root.join("rank0.out"))?,
+            "rank 0 input\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("rank0.err"))?,
+            "done: run alpine\ndone: run alpine\n"
+        );
+
+        let merged = Redirect {
+            stdin: Some(root.join("input")),
+            stdout: Some(OutputFile::truncate(root.join("rank1.log"))),
+            stderr: None,
+            merge_stderr: true,
+        };
+        assert!(run_redirected(["alpine"], &merged, Some(&p_ctx))?.success());
+        assert_eq!(
+            fs::read_to_string(root.join("rank1.log"))?,
+            "rank 0 input\ndone: run alpine\n"
+        );
+
+        // The container reads stdin, without a terminal
+        let edf = test_edf("alpine.toml");
+        let c_ctx = ContainerCtx {
+            name: String::from("rank1"),
+            ..Default::default()
+        };
+        assert!(run_from_edf_redirected(&edf, Some(&p_ctx), &c_ctx, ["cat"], &merged)?.success());
+        let log = fs::read_to_string(root.join("rank1.log"))?;
+        assert!(log.starts_with("rank 0 input\ndone: run --rm --interactive "));
+
+        assert!(start_attached_redirected("rank1", &merged, Some(&p_ctx))?.success());
+        assert_eq!(
+            fs::read_to_string(root.join("rank1.log"))?,
+            "rank 0 input\ndone: start --attach --interactive rank1\n"
+        );
+        let err = start_attached_redirected("rank2", &merged, Some(&p_ctx)).unwrap_err();
+        assert!(err.to_string().contains("stdin closed"));
+
+        let no_stdout = Redirect {
+            merge_stderr: true,
+            ..Default::default()
+        };
+        assert!(run_redirected(["alpine"], &no_stdout, Some(&p_ctx)).is_err());
+        let both = Redirect {
+            stderr: Some(OutputFile::truncate(root.join("rank1.err"))),
+            ..merged
+        };
+        assert!(run_redirected(["alpine"], &both, Some(&p_ctx)).is_err());
+
+        // Nothing is created when the redirect cannot be applied
+        let unique = ContainerCtx {
+            name_policy: NamePolicy::UniqueSuffix,
+            ..c_ctx
+        };
+        let missing = Redirect {
+            stdin: Some(root.join("missing")),
+            ..Default::default()
+        };
+        let calls = podman.calls().len();
+        assert!(run_from_edf_redirected(&edf, Some(&p_ctx), &unique, ["cat"], &missing).is_err());
+        assert!(run_from_edf_redirected(&edf, Some(&p_ctx), &unique, ["cat"], &both).is_err());
+        assert_eq!(podman.calls().len(), calls);
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+// Resource limits of a container, rendered into `podman run` flags
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::env;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceLimits {
+    // Number of CPUs, may be fractional
+    pub cpus: Option<f64>,
+    // Bytes
+    pub memory: Option<u64>,
+    // -1 for unlimited
+    pub pids_limit: Option<i64>,
+    // Cpuset list, e.g. "0-3,8"
+    pub cpuset_cpus: Option<String>,
+    // Bytes. Podman defaults to 64MB, which is too small for many MPI shared memory transports.
+    pub shm_size: Option<u64>,
+    pub ulimits: Vec<Ulimit>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ulimit {
+    // e.g. "nofile", "memlock", "stack"
+    pub name: String,
+    // -1 for unlimited
+    pub soft: i64,
+    pub hard: i64,
+}
+
+impl Ulimit {
+    pub fn new(name: impl Into<String>, soft: i64, hard: i64) -> Self {
+        Self {
+            name: name.into(),
+            soft,
+            hard,
+        }
+    }
+
+    pub fn unlimited(name: impl Into<String>) -> Self {
+        Self::new(name, -1, -1)
+    }
+
+    // "<name>=<soft>:<hard>" as taken by `--ulimit`
+    pub fn to_arg(&self) -> String {
+        format!("{}={}:{}", self.name, self.soft, self.hard)
+    }
+}
+
+impl ResourceLimits {
+    // Limits matching the Slurm allocation of the current job step, from the variables Slurm
+    // exports to it: the CPUs the task is bound to (SLURM_CPU_BIND_LIST, all tasks of the node
+    // unless SLURM_LOCALID is set) and the allocated memory (SLURM_MEM_PER_NODE, or
+    // SLURM_MEM_PER_CPU times SLURM_CPUS_ON_NODE).
+    // Nothing is set outside of a Slurm job, nor the cpuset of an unbound step.
+    pub fn from_slurm_env() -> anyhow::Result<Self> {
+        from_slurm_vars(|var| env::var(var).ok())
+    }
+}
+
+fn from_slurm_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<ResourceLimits> {
+    let mut limits = ResourceLimits::default();
+    if var("SLURM_JOB_ID").is_none() {
+        return Ok(limits);
+    }
+
+    // Slurm exports memory amounts in megabytes
+    let mb = |name: &str| -> anyhow::Result<Option<u64>> {
+        var(name)
+            .map(|val| {
+                val.parse::<u64>()
+                    .with_context(|| format!("invalid {name}: {val}"))
+            })
+            .transpose()
+    };
+
+    limits.memory = match (mb("SLURM_MEM_PER_NODE")?, mb("SLURM_MEM_PER_CPU")?) {
+        (Some(per_node), _) => Some(per_node),
+        (None, Some(per_cpu)) => mb("SLURM_CPUS_ON_NODE")?.map(|cpus| per_cpu * cpus),
+        (None, None) => None,
+    }
+    .filter(|&mem| mem > 0)
+    .map(|mem| mem * 1024 * 1024);
+
+    if let Some(list) = var("SLURM_CPU_BIND_LIST").filter(|list| !list.is_empty()) {
+        let map = var("SLURM_CPU_BIND_TYPE").is_some_and(|ty| ty.contains("map_cpu"));
+        let local_id = var("SLURM_LOCALID")
+            .map(|id| {
+                id.parse::<usize>()
+                    .with_context(|| format!("invalid SLURM_LOCALID: {id}"))
+            })
+            .transpose()?;
+        let cpus = bound_cpus(&list, map, local_id)
+            .with_context(|| format!("invalid SLURM_CPU_BIND_LIST: {list}"))?;
+        limits.cpuset_cpus = Some(cpuset(&cpus)).filter(|set| !set.is_empty());
+    }
+
+    Ok(limits)
+}
+
+// CPUs of a SLURM_CPU_BIND_LIST, one entry per local task: hex masks ("0x0F,0xF0"), or CPU IDs
+// ("0,4") when binding with map_cpu
+fn bound_cpus(list: &str, map: bool, local_id: Option<usize>) -> Option<BTreeSet<u32>> {
+    let entries: Vec<&str> = list.split(',').map(str::trim).collect();
+    let entries = match local_id {
+        Some(id) => vec![*entries.get(id)?],
+        None => entries,
+    };
+
+    let mut cpus = BTreeSet::new();
+    for entry in entries {
+        if map {
+            cpus.insert(entry.parse().ok()?);
+            continue;
+        }
+        let hex = entry.strip_prefix("0x").unwrap_or(entry);
+        for (i, digit) in hex.chars().rev().enumerate() {
+            let digit = digit.to_digit(16)?;
+            let base = u32::try_from(i).ok()? * 4;
+            cpus.extend(
+                (0..4)
+                    .filter(|bit| digit & (1 << bit) != 0)
+                    .map(|bit| base + bit),
+            );
+        }
+    }
+    Some(cpus)
+}
+
+// Cpuset list as taken by `--cpuset-cpus`, e.g. "0-3,8"
+fn cpuset(cpus: &BTreeSet<u32>) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .into_iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first}-{last}")
+            }
+        })
+        .collect();
+    ranges.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn test_from_slurm_vars() -> anyhow::Result<()> {
+        let outside = from_slurm_vars(vars(&[("SLURM_CPU_BIND_LIST", "0xF")]))?;
+        assert_eq!(outside, ResourceLimits::default());
+
+        let per_node = from_slurm_vars(vars(&[
+            ("SLURM_JOB_ID", "42"),
+            ("SLURM_MEM_PER_NODE", "4096"),
+            ("SLURM_CPU_BIND_TYPE", "mask_cpu:"),
+            ("SLURM_CPU_BIND_LIST", "0x00F,0x100"),
+        ]))?;
+        assert_eq!(per_node.memory, Some(4096 * 1024 * 1024));
+        assert_eq!(per_node.cpuset_cpus.as_deref(), Some("0-3,8"));
+
+        let per_cpu = from_slurm_vars(vars(&[
+            ("SLURM_JOB_ID", "42"),
+            ("SLURM_MEM_PER_CPU", "1000"),
+            ("SLURM_CPUS_ON_NODE", "4"),
+        ]))?;
+        assert_eq!(per_cpu.memory, Some(4000 * 1024 * 1024));
+        assert_eq!(per_cpu.cpuset_cpus, None);
+
+        let task = from_slurm_vars(vars(&[
+            ("SLURM_JOB_ID", "42"),
+            ("SLURM_CPU_BIND_LIST", "0x3,0xC"),
+            ("SLURM_LOCALID", "1"),
+        ]))?;
+        assert_eq!(task.cpuset_cpus.as_deref(), Some("2-3"));
+
+        let mapped = from_slurm_vars(vars(&[
+            ("SLURM_JOB_ID", "42"),
+            ("SLURM_CPU_BIND_TYPE", "map_cpu:"),
+            ("SLURM_CPU_BIND_LIST", "4,0,5"),
+        ]))?;
+        assert_eq!(mapped.cpuset_cpus.as_deref(), Some("0,4-5"));
+
+        for invalid in [
+            [("SLURM_MEM_PER_NODE", "4G")],
+            [("SLURM_CPU_BIND_LIST", "0xZZ")],
+        ] {
+            let invalid = from_slurm_vars(vars(&[[("SLURM_JOB_ID", "42")], invalid].concat()));
+            assert!(invalid.is_err());
+        }
+        Ok(())
+    }
+}
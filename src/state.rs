@@ -0,0 +1,192 @@
+// Container state read straight from storage metadata and runtime files, without spawning
+// podman whenever the storage locations can be determined.
+use crate::storage::{self, StorageDriver};
+use crate::{PodmanCtx, info};
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+pub struct ContainerStateReader {
+    graphroot: PathBuf,
+    runroot: PathBuf,
+    driver: StorageDriver,
+}
+
+impl ContainerStateReader {
+    // Storage locations are taken from the context when set, then from the podman defaults
+    // (e.g. $XDG_RUNTIME_DIR/containers as rootless runroot) and only then from `podman info`
+    pub fn new(podman_ctx: Option<&PodmanCtx>) -> anyhow::Result<Self> {
+        let graphroot = match podman_ctx.and_then(|ctx| ctx.graphroot.clone()) {
+            Some(graphroot) => graphroot,
+            None => default_graphroot()
+                .filter(|g| StorageDriver::detect(g).is_some())
+                .map_or_else(|| podman_info_path("{{.Store.GraphRoot}}", podman_ctx), Ok)?,
+        };
+        let Some(driver) = StorageDriver::detect(&graphroot) else {
+            anyhow::bail!(
+                "no overlay or vfs container metadata in {}",
+                graphroot.display()
+            );
+        };
+
+        let runroot = match podman_ctx.and_then(|ctx| ctx.runroot.clone()) {
+            Some(runroot) => runroot,
+            None => default_runroot()
+                .filter(|r| r.is_dir())
+                .map_or_else(|| podman_info_path("{{.Store.RunRoot}}", podman_ctx), Ok)?,
+        };
+
+        Ok(Self {
+            graphroot,
+            runroot,
+            driver,
+        })
+    }
+
+    pub fn graphroot(&self) -> &Path {
+        &self.graphroot
+    }
+
+    pub fn runroot(&self) -> &Path {
+        &self.runroot
+    }
+
+    pub fn driver(&self) -> StorageDriver {
+        self.driver
+    }
+
+    // Resolve a container name, full ID or unambiguous ID prefix to the full ID
+    pub fn resolve_id(&self, name_or_id: &str) -> anyhow::Result<String> {
+        let containers = storage::read_containers(&self.graphroot, self.driver)?;
+
+        if let Some(cnt) = containers
+            .iter()
+            .find(|c| c.id == name_or_id || c.names.iter().any(|n| n == name_or_id))
+        {
+            return Ok(cnt.id.clone());
+        }
+
+        let mut by_prefix = containers.iter().filter(|c| c.id.starts_with(name_or_id));
+        match (by_prefix.next(), by_prefix.next()) {
+            (Some(cnt), None) => Ok(cnt.id.clone()),
+            (Some(_), Some(_)) => anyhow::bail!("container ID prefix {name_or_id} is ambiguous"),
+            (None, _) => anyhow::bail!("no container with name or ID {name_or_id} found"),
+        }
+    }
+
+    // PID of a running container. A custom pidfile, i.e. the `ContainerCtx::pidfile` given to
+    // `podman run`, is read as is; otherwise the default pidfile in runroot is used.
+    pub fn container_pid(&self, name_or_id: &str, pidfile: Option<&Path>) -> anyhow::Result<u32> {
+        if let Some(pidfile) = pidfile {
+            return read_pidfile(pidfile);
+        }
+
+        let id = self.resolve_id(name_or_id)?;
+        let pidfile = self
+            .runroot
+            .join(self.driver.containers_dir())
+            .join(id)
+            .join("userdata/pidfile");
+        read_pidfile(&pidfile)
+    }
+}
+
+// Pidfiles may or may not end with a newline depending on who wrote them
+pub fn read_pidfile(path: &Path) -> anyhow::Result<u32> {
+    let pid = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("cannot read pidfile {}: {e}", path.display()))?;
+    let pid = pid
+        .trim()
+        .parse()
+        .map_err(|e| anyhow::anyhow!("invalid pidfile {}: {e}", path.display()))?;
+    Ok(pid)
+}
+
+fn is_rootless() -> bool {
+    fs::metadata("/proc/self").is_ok_and(|m| m.uid() != 0)
+}
+
+fn default_graphroot() -> Option<PathBuf> {
+    if !is_rootless() {
+        return Some(PathBuf::from("/var/lib/containers/storage"));
+    }
+
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(data_home.join("containers/storage"))
+}
+
+fn default_runroot() -> Option<PathBuf> {
+    if !is_rootless() {
+        return Some(PathBuf::from("/run/containers/storage"));
+    }
+
+    env::var_os("XDG_RUNTIME_DIR").map(|dir| Path::new(&dir).join("containers"))
+}
+
+fn podman_info_path(format: &str, podman_ctx: Option<&PodmanCtx>) -> anyhow::Result<PathBuf> {
+    let output = info(Some(format), podman_ctx);
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("podman info failed: {}", stderr.trim());
+    }
+
+    let path = str::from_utf8(&output.stdout)?.trim();
+    Ok(PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+
+    #[test]
+    fn test_container_pid_from_storage() -> anyhow::Result<()> {
+        // Fails if podman info were used
+        let podman = FakePodman::new("state", "exit 125\n");
+        let root = podman.dir();
+        let graphroot = root.join("graphroot");
+        let runroot = root.join("runroot");
+        let id = "4f0c3b1e2a9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b";
+
+        fs::create_dir_all(graphroot.join("vfs-containers"))?;
+        fs::write(
+            graphroot.join("vfs-containers/containers.json"),
+            format!(r#"[{{"id":"{id}","names":["sarus_state_test"],"image":"abcd"}}]"#),
+        )?;
+        fs::create_dir_all(runroot.join("vfs-containers").join(id).join("userdata"))?;
+        fs::write(
+            runroot
+                .join("vfs-containers")
+                .join(id)
+                .join("userdata/pidfile"),
+            "4242\n",
+        )?;
+
+        let p_ctx = PodmanCtx {
+            graphroot: Some(graphroot),
+            runroot: Some(runroot),
+            ..podman.ctx()
+        };
+
+        let reader = ContainerStateReader::new(Some(&p_ctx))?;
+        assert_eq!(reader.driver(), StorageDriver::Vfs);
+        assert_eq!(reader.resolve_id("sarus_state_test")?, id);
+        assert_eq!(reader.resolve_id("4f0c3b")?, id);
+        assert!(reader.resolve_id("not_there").is_err());
+
+        assert_eq!(reader.container_pid("sarus_state_test", None)?, 4242);
+        assert_eq!(reader.container_pid(id, None)?, 4242);
+
+        let custom_pidfile = root.join("custom-pidfile");
+        fs::write(&custom_pidfile, "1234")?;
+        assert_eq!(
+            reader.container_pid("sarus_state_test", Some(&custom_pidfile))?,
+            1234
+        );
+        Ok(())
+    }
+}
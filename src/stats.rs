@@ -0,0 +1,248 @@
+// Resource usage of running containers as reported by `podman stats`, whose JSON output is
+// humanized, e.g. "1.5GB / 33.4GB"
+use crate::duration::parse_duration;
+use crate::{PodmanCtx, checked_output, commands};
+use serde::Deserialize;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// Fields podman could not determine (e.g. network I/O of a container without network
+// namespace, shown as "--") are None
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsSample {
+    pub id: String,
+    pub name: String,
+    pub time: SystemTime,
+    pub cpu_percent: Option<f64>,
+    pub cpu_time: Option<Duration>,
+    // Bytes
+    pub mem_usage: Option<u64>,
+    pub mem_limit: Option<u64>,
+    pub mem_percent: Option<f64>,
+    pub net_input: Option<u64>,
+    pub net_output: Option<u64>,
+    pub block_input: Option<u64>,
+    pub block_output: Option<u64>,
+    pub pids: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct RawStats {
+    id: String,
+    name: String,
+    cpu_time: String,
+    cpu_percent: String,
+    mem_usage: String,
+    mem_percent: String,
+    net_io: String,
+    block_io: String,
+    pids: String,
+}
+
+impl RawStats {
+    fn parse(self, time: SystemTime) -> StatsSample {
+        let (mem_usage, mem_limit) = parse_size_pair(&self.mem_usage);
+        let (net_input, net_output) = parse_size_pair(&self.net_io);
+        let (block_input, block_output) = parse_size_pair(&self.block_io);
+        StatsSample {
+            id: self.id,
+            name: self.name,
+            time,
+            cpu_percent: parse_percent(&self.cpu_percent),
+            cpu_time: parse_duration(&self.cpu_time),
+            mem_usage,
+            mem_limit,
+            mem_percent: parse_percent(&self.mem_percent),
+            net_input,
+            net_output,
+            block_input,
+            block_output,
+            pids: self.pids.trim().parse().ok(),
+        }
+    }
+}
+
+pub fn stats(name: &str, podman_ctx: Option<&PodmanCtx>) -> anyhow::Result<StatsSample> {
+    let output = checked_output(&mut commands::stats(name, podman_ctx), "podman stats")?;
+    let time = SystemTime::now();
+    let raw: Vec<RawStats> = serde_json::from_slice(&output.stdout)?;
+    let Some(raw) = raw.into_iter().next() else {
+        anyhow::bail!("podman stats returned no data for container {name}");
+    };
+    Ok(raw.parse(time))
+}
+
+// Sample the container every `interval` and send the samples as they are taken, e.g. from a
+// thread::scope() next to the job step. Ends once the container stopped or was removed, or
+// once the receiver is dropped; fails if the container cannot be sampled otherwise.
+pub fn sample_stats(
+    name: &str,
+    interval: Duration,
+    podman_ctx: Option<&PodmanCtx>,
+    samples: Sender<StatsSample>,
+) -> anyhow::Result<()> {
+    let mut sample = stats(name, podman_ctx)?;
+    while samples.send(sample).is_ok() {
+        thread::sleep(interval);
+        sample = match stats(name, podman_ctx) {
+            Ok(sample) => sample,
+            Err(_) if !running(name, podman_ctx)? => return Ok(()),
+            Err(e) => return Err(e),
+        };
+    }
+    Ok(())
+}
+
+fn running(name: &str, podman_ctx: Option<&PodmanCtx>) -> anyhow::Result<bool> {
+    let exists = commands::container_exists(name, podman_ctx).output()?;
+    match exists.status.code() {
+        Some(0) => {}
+        Some(1) => return Ok(false),
+        _ => {
+            let stderr = String::from_utf8_lossy(&exists.stderr);
+            anyhow::bail!("podman container exists failed: {}", stderr.trim());
+        }
+    }
+    let output = checked_output(
+        &mut commands::inspect(name, Some("{{.State.Running}}"), podman_ctx),
+        "podman inspect",
+    )?;
+    Ok(str::from_utf8(&output.stdout)?.trim() == "true")
+}
+
+fn parse_percent(val: &str) -> Option<f64> {
+    val.trim().strip_suffix('%')?.parse().ok()
+}
+
+// e.g. "1.229MB / 33.4GB"
+fn parse_size_pair(val: &str) -> (Option<u64>, Option<u64>) {
+    match val.split_once('/') {
+        Some((first, second)) => (parse_size(first), parse_size(second)),
+        None => (None, None),
+    }
+}
+
+// Decimal ("kB", "MB") as well as binary ("KiB", "MiB") units
+fn parse_size(val: &str) -> Option<u64> {
+    let val = val.trim();
+    let split = val.find(|c: char| c.is_ascii_alphabetic())?;
+    let (num, unit) = val.split_at(split);
+    let num: f64 = num.trim().parse().ok()?;
+    let factor: f64 = match unit {
+        "B" => 1.0,
+        "kB" | "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "PB" => 1e15,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        "PiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((num * factor).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+    use std::fs;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_parse_stats() {
+        let raw: Vec<RawStats> = serde_json::from_str(
+            r#"[{"id":"aaaa1111","name":"rank0","cpu_time":"1m2.5s","cpu_percent":"99.53%",
+                 "avg_cpu":"97.10%","mem_usage":"1.229GB / 33.4GB","mem_percent":"3.68%",
+                 "net_io":"-- / --","block_io":"12.3kB / 0B","pids":"17"}]"#,
+        )
+        .unwrap();
+        let sample = raw
+            .into_iter()
+            .next()
+            .unwrap()
+            .parse(SystemTime::UNIX_EPOCH);
+
+        assert_eq!(sample.name, "rank0");
+        assert_eq!(sample.cpu_percent, Some(99.53));
+        assert_eq!(sample.cpu_time, Some(Duration::from_millis(62_500)));
+        assert_eq!(sample.mem_usage, Some(1_229_000_000));
+        assert_eq!(sample.mem_limit, Some(33_400_000_000));
+        assert_eq!(sample.mem_percent, Some(3.68));
+        assert_eq!((sample.net_input, sample.net_output), (None, None));
+        assert_eq!(sample.block_input, Some(12_300));
+        assert_eq!(sample.block_output, Some(0));
+        assert_eq!(sample.pids, Some(17));
+
+        assert_eq!(parse_size("1.5MiB"), Some(1_572_864));
+    }
+
+    #[test]
+    fn test_sample_stats() -> anyhow::Result<()> {
+        // Stats fail after `limit` samples: the container is gone, unless `broken` exists
+        let podman = FakePodman::new(
+            "stats",
+            r#"case "$1" in
+    container) [ -e "$test_dir/broken" ]; exit $? ;;
+    inspect) echo true; exit ;;
+esac
+echo "$@" | grep -q "^stats --no-stream --format json rank0$" || exit 125
+n=$(cat "$test_dir/count" 2>/dev/null || echo 0)
+if [ "$n" -ge $(cat "$test_dir/limit") ]; then
+    echo "Error: cannot get stats" >&2
+    exit 125
+fi
+echo $((n + 1)) > "$test_dir/count"
+echo '[{"id":"aaaa1111","name":"rank0","pids":"'$((n + 1))'"}]'
+"#,
+        );
+        let p_ctx = podman.ctx();
+        let dir = podman.dir();
+        let interval = Duration::from_millis(10);
+        let restart = |limit: u32| {
+            let _ = fs::remove_file(dir.join("count"));
+            fs::write(dir.join("limit"), limit.to_string()).unwrap();
+        };
+
+        restart(3);
+        let (tx, rx) = mpsc::channel();
+        sample_stats("rank0", interval, Some(&p_ctx), tx)?;
+        let samples: Vec<StatsSample> = rx.into_iter().collect();
+        let pids: Vec<Option<u64>> = samples.iter().map(|s| s.pids).collect();
+        assert_eq!(pids, [Some(1), Some(2), Some(3)]);
+        assert!(samples.windows(2).all(|w| w[0].time <= w[1].time));
+
+        // Stopped early by the supervisor
+        restart(1000);
+        thread::scope(|scope| {
+            let (tx, rx) = mpsc::channel();
+            let sampler = scope.spawn(|| sample_stats("rank0", interval, Some(&p_ctx), tx));
+            assert_eq!(rx.iter().take(2).count(), 2);
+            drop(rx);
+            assert!(sampler.join().unwrap().is_ok());
+        });
+        assert!(
+            fs::read_to_string(dir.join("count"))?
+                .trim()
+                .parse::<u32>()?
+                <= 3
+        );
+
+        // Still running, so sampling failed
+        restart(2);
+        fs::write(dir.join("broken"), "")?;
+        let (tx, _rx) = mpsc::channel();
+        assert!(sample_stats("rank0", interval, Some(&p_ctx), tx).is_err());
+
+        // Not running at all
+        restart(0);
+        let (tx, _rx) = mpsc::channel();
+        assert!(sample_stats("rank0", interval, Some(&p_ctx), tx).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+// Read-only access to containers/storage metadata, i.e. the JSON files podman keeps inside a
+// graphroot or an additional image store.
+use crate::PodmanCtx;
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+const IMAGES_DIR: &str = "overlay-images";
+const IMAGES_JSON: &str = "overlay-images/images.json";
+
+// Subset of an image record of `images.json`
+#[derive(Clone, Debug, Deserialize)]
+pub struct StoredImage {
+    pub id: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub digests: Vec<String>,
+}
+
+// Subset of a container record of `containers.json`
+#[derive(Clone, Debug, Deserialize)]
+pub struct StoredContainer {
+    pub id: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub names: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageDriver {
+    Overlay,
+    Vfs,
+}
+
+impl StorageDriver {
+    // Driver whose container metadata is found in `graphroot`
+    pub fn detect(graphroot: &Path) -> Option<Self> {
+        [Self::Overlay, Self::Vfs]
+            .into_iter()
+            .find(|driver| graphroot.join(driver.containers_dir()).is_dir())
+    }
+
+    // Directory holding the per-container data, both in graphroot and in runroot
+    pub fn containers_dir(self) -> &'static str {
+        match self {
+            Self::Overlay => "overlay-containers",
+            Self::Vfs => "vfs-containers",
+        }
+    }
+}
+
+impl StoredImage {
+    // Whether `reference` designates this image. Accepted forms are those of `podman image
+    // exists`: full or abbreviated ID, optionally prefixed by "sha256:", and names with an
+    // optional tag and/or digest. Short names are resolved as docker.io or localhost images,
+    // the registries.conf search list is not taken into account.
+    pub fn matches(&self, reference: &str) -> bool {
+        let id = reference.strip_prefix("sha256:").unwrap_or(reference);
+        if id.len() >= 3 && id.bytes().all(|b| b.is_ascii_hexdigit()) && self.id.starts_with(id) {
+            return true;
+        }
+
+        let reference = ImageRef::parse(reference);
+        reference.candidates().iter().any(|repo| {
+            if let Some(digest) = reference.digest {
+                let has_digest = self.digest.as_deref() == Some(digest)
+                    || self.digests.iter().any(|d| d == digest);
+                has_digest && self.names.iter().any(|n| ImageRef::parse(n).repo == repo)
+            } else {
+                let tagged = format!("{repo}:{}", reference.tag.unwrap_or("latest"));
+                self.names.contains(&tagged)
+            }
+        })
+    }
+}
+
+// Reference split as "repo[:tag][@digest]"
+struct ImageRef<'a> {
+    repo: &'a str,
+    tag: Option<&'a str>,
+    digest: Option<&'a str>,
+}
+
+impl<'a> ImageRef<'a> {
+    fn parse(reference: &'a str) -> Self {
+        let (name, digest) = match reference.split_once('@') {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (reference, None),
+        };
+
+        // A colon after the last slash separates the tag, a colon before is a registry port
+        let last_slash = name.rfind('/').map_or(0, |i| i + 1);
+        match name[last_slash..].rfind(':') {
+            Some(i) => Self {
+                repo: &name[..last_slash + i],
+                tag: Some(&name[last_slash + i + 1..]),
+                digest,
+            },
+            None => Self {
+                repo: name,
+                tag: None,
+                digest,
+            },
+        }
+    }
+
+    // Fully qualified repositories the reference may resolve to
+    fn candidates(&self) -> Vec<String> {
+        let first = self.repo.split('/').next().unwrap_or_default();
+        let qualified = self.repo.contains('/')
+            && (first.contains('.') || first.contains(':') || first == "localhost");
+
+        if qualified {
+            vec![self.repo.to_string()]
+        } else if self.repo.contains('/') {
+            vec![
+                format!("docker.io/{}", self.repo),
+                format!("localhost/{}", self.repo),
+            ]
+        } else {
+            vec![
+                format!("docker.io/library/{}", self.repo),
+                format!("localhost/{}", self.repo),
+            ]
+        }
+    }
+}
+
+// Canonical form of a reference, e.g. "docker.io/library/alpine:latest" for "alpine".
+// IDs are returned unchanged. Short names are assumed to come from docker.io.
+pub fn normalize_reference(reference: &str) -> String {
+    let id = reference.strip_prefix("sha256:").unwrap_or(reference);
+    if id.len() >= 3 && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return id.to_string();
+    }
+
+    let reference = ImageRef::parse(reference);
+    let mut normalized = reference.candidates().swap_remove(0);
+    if reference.tag.is_some() || reference.digest.is_none() {
+        normalized.push(':');
+        normalized.push_str(reference.tag.unwrap_or("latest"));
+    }
+    if let Some(digest) = reference.digest {
+        normalized.push('@');
+        normalized.push_str(digest);
+    }
+    normalized
+}
+
+// Images recorded in an overlay store. A store where no image was ever written has no
+// images.json yet, which is reported as an empty store.
+pub fn read_images(store: &Path) -> anyhow::Result<Vec<StoredImage>> {
+    let path = store.join(IMAGES_JSON);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+
+    serde_json::from_slice(&data).with_context(|| format!("cannot parse {}", path.display()))
+}
+
+pub fn read_containers(
+    graphroot: &Path,
+    driver: StorageDriver,
+) -> anyhow::Result<Vec<StoredContainer>> {
+    let path = graphroot
+        .join(driver.containers_dir())
+        .join("containers.json");
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+
+    serde_json::from_slice(&data).with_context(|| format!("cannot parse {}", path.display()))
+}
+
+pub fn find_image(store: &Path, reference: &str) -> anyhow::Result<Option<StoredImage>> {
+    Ok(read_images(store)?
+        .into_iter()
+        .find(|img| img.matches(reference)))
+}
+
+// Look `reference` up in the metadata of graphroot and of the read-only stores, without
+// spawning podman. Only finding the image is conclusive: podman may also resolve it from stores
+// of storage.conf, which are not scanned, and metadata may be missing (graphroot not given or
+// not an overlay store) or unreadable.
+pub fn image_found(reference: &str, podman_ctx: &PodmanCtx) -> bool {
+    let Some(graphroot) = podman_ctx.graphroot.as_deref() else {
+        return false;
+    };
+    if !graphroot.join(IMAGES_DIR).is_dir() {
+        return false;
+    }
+
+    std::iter::once(graphroot)
+        .chain(podman_ctx.ro_stores.iter().map(|s| s.as_path()))
+        .any(|store| matches!(find_image(store, reference), Ok(Some(_))))
+}
+
+// Go marshals empty slices as `null`
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_matches() {
+        let image = StoredImage {
+            id: String::from("9234e8fb04c47cfe0f49931e4ac7eb76fa904e33b7f8576aec0501c085f02516"),
+            names: vec![
+                String::from("docker.io/library/alpine:3.22"),
+                String::from("registry.local:5000/hpc/app:1.0"),
+            ],
+            digest: Some(String::from(
+                "sha256:4bcff63911fcb4448bd4fdacec207030997caf25e9bea4045fa6c8c44de311d1",
+            )),
+            digests: Vec::new(),
+        };
+
+        assert!(image.matches("9234e8fb04c4"));
+        assert!(
+            image
+                .matches("sha256:9234e8fb04c47cfe0f49931e4ac7eb76fa904e33b7f8576aec0501c085f02516")
+        );
+        assert!(image.matches("alpine:3.22"));
+        assert!(image.matches("library/alpine:3.22"));
+        assert!(image.matches("docker.io/library/alpine:3.22"));
+        assert!(image.matches("registry.local:5000/hpc/app:1.0"));
+        assert!(image.matches(
+            "alpine@sha256:4bcff63911fcb4448bd4fdacec207030997caf25e9bea4045fa6c8c44de311d1"
+        ));
+
+        assert!(!image.matches("alpine"));
+        assert!(!image.matches("alpine:3.21"));
+        assert!(!image.matches("quay.io/library/alpine:3.22"));
+        assert!(!image.matches("hpc/app:1.0"));
+        assert!(!image.matches(
+            "ubuntu@sha256:4bcff63911fcb4448bd4fdacec207030997caf25e9bea4045fa6c8c44de311d1"
+        ));
+        assert!(!image.matches("abcdef"));
+    }
+
+    #[test]
+    fn test_normalize_reference() {
+        assert_eq!(
+            normalize_reference("alpine"),
+            "docker.io/library/alpine:latest"
+        );
+        assert_eq!(
+            normalize_reference("library/alpine:3.22"),
+            "docker.io/library/alpine:3.22"
+        );
+        assert_eq!(
+            normalize_reference("registry.local:5000/hpc/app@sha256:4bcff639"),
+            "registry.local:5000/hpc/app@sha256:4bcff639"
+        );
+        assert_eq!(normalize_reference("sha256:9234e8fb04c4"), "9234e8fb04c4");
+    }
+}
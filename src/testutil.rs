@@ -0,0 +1,99 @@
+// Scaffolding shared by the unit tests: temporary directories removed even when a test fails,
+// and a fake podman logging how it was called
+use crate::{LockConfig, PodmanCtx};
+use raster::EDF;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+// "sarus-<name>-test-<pid>" in the temporary directory, removed on drop
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sarus-{name}-test-{}", std::process::id()));
+        // Left behind by a previous run that was killed
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("Failed creating test directory");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn write_script(path: &Path, script: &str) {
+    fs::write(path, script).expect("Failed writing script");
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .expect("Failed making script executable");
+}
+
+// A podman appending its arguments to calls.log, then running `body` with $test_dir set to the
+// test directory, e.g. to answer from files the test writes there
+pub struct FakePodman {
+    dir: TestDir,
+}
+
+impl FakePodman {
+    pub fn new(name: &str, body: &str) -> Self {
+        let dir = TestDir::new(name);
+        write_script(
+            &dir.join("podman"),
+            &format!(
+                "#!/bin/sh\ntest_dir='{}'\necho \"$@\" >> \"$test_dir/calls.log\"\n{body}",
+                dir.path().display()
+            ),
+        );
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &TestDir {
+        &self.dir
+    }
+
+    pub fn ctx(&self) -> PodmanCtx {
+        podman_ctx(self.dir.join("podman"))
+    }
+
+    // One line of arguments per call, oldest first
+    pub fn calls(&self) -> Vec<String> {
+        fs::read_to_string(self.dir.join("calls.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+// Nothing but `podman_path` set
+pub fn podman_ctx(podman_path: PathBuf) -> PodmanCtx {
+    PodmanCtx {
+        podman_path,
+        module: None,
+        graphroot: None,
+        runroot: None,
+        parallax_mount_program: None,
+        ro_stores: Vec::new(),
+        parallax_lock: LockConfig::default(),
+        podman_env: None,
+    }
+}
+
+// One of tests/edf
+pub fn test_edf(name: &str) -> EDF {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/edf")
+        .join(name);
+    raster::render(path.to_string_lossy().into_owned()).expect("Failed rendering EDF")
+}
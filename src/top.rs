@@ -0,0 +1,164 @@
+// Processes running inside a container as listed by `podman top`, e.g. to find out what a hung
+// rank is doing, unlike get_container_pid() which only gives the init process
+use crate::duration::parse_duration;
+use crate::{PodmanCtx, checked_output, commands};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Podman's AIX format descriptors, with `args` last as it may contain spaces
+pub const DEFAULT_TOP_DESCRIPTORS: &[&str] = &["pid", "user", "pcpu", "etime", "hpid", "args"];
+
+// Descriptors of the command, whose values may contain spaces: the name of a process is set by
+// the process itself
+const SPACED_DESCRIPTORS: &[&str] = &["args", "cmd", "command", "comm", "ucmd", "ucomm"];
+
+// Fields whose descriptor was not asked for, or which podman could not determine (e.g. "?"),
+// are None
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TopRow {
+    pub pid: Option<u32>,
+    pub user: Option<String>,
+    pub comm: Option<String>,
+    pub args: Option<String>,
+    pub cpu_percent: Option<f64>,
+    pub elapsed: Option<Duration>,
+    // PID in the namespace of the host, e.g. for gdb or py-spy from outside the container
+    pub host_pid: Option<u32>,
+    // All fields as printed, by descriptor
+    pub fields: BTreeMap<String, String>,
+}
+
+// `descriptors` as taken by `podman top`, DEFAULT_TOP_DESCRIPTORS if empty. Values are split on
+// whitespace, so a descriptor whose values may contain spaces, e.g. "comm" or "args", is
+// rejected unless it comes last.
+pub fn top(
+    name: &str,
+    descriptors: &[&str],
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<Vec<TopRow>> {
+    let descriptors = if descriptors.is_empty() {
+        DEFAULT_TOP_DESCRIPTORS
+    } else {
+        descriptors
+    };
+    let (_, leading) = descriptors.split_last().expect("descriptors are not empty");
+    if let Some(spaced) = leading.iter().find(|d| SPACED_DESCRIPTORS.contains(d)) {
+        anyhow::bail!(
+            "top descriptor {spaced} may have values containing spaces, it must come last"
+        );
+    }
+    let output = checked_output(
+        &mut commands::top(name, descriptors, podman_ctx),
+        "podman top",
+    )?;
+    let stdout = str::from_utf8(&output.stdout)?;
+
+    // The first line is the header
+    Ok(stdout
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse_row(line, descriptors))
+        .collect())
+}
+
+fn parse_row(line: &str, descriptors: &[&str]) -> TopRow {
+    let mut row = TopRow::default();
+    let mut rest = line.trim();
+    for (i, &descriptor) in descriptors.iter().enumerate() {
+        let val = if i + 1 == descriptors.len() {
+            std::mem::take(&mut rest)
+        } else {
+            let (val, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            rest = tail.trim_start();
+            val
+        };
+        row.fields.insert(descriptor.to_string(), val.to_string());
+
+        let text = Some(val.to_string()).filter(|v| !v.is_empty() && v != "?");
+        match descriptor {
+            "pid" => row.pid = val.parse().ok(),
+            "user" => row.user = text,
+            "comm" => row.comm = text,
+            "args" => row.args = text,
+            "pcpu" => row.cpu_percent = val.parse().ok(),
+            "etime" => row.elapsed = parse_duration(val),
+            "hpid" => row.host_pid = val.parse().ok(),
+            _ => {}
+        }
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+
+    #[test]
+    fn test_top() -> anyhow::Result<()> {
+        let podman = FakePodman::new(
+            "top",
+            r#"case "$*" in
+    *args)
+        echo "PID   USER   %CPU    ELAPSED          HPID     COMMAND"
+        echo "1     root   98.765  1h2m3.5s         41234    mpi_app --ranks 4 -v"
+        echo "27    1000   0.000   250ms            ?        sleep 3600"
+        ;;
+    *comm)
+        echo "PID   COMMAND"
+        echo "1     mpi app"
+        ;;
+    *)
+        echo "PID   USER"
+        echo "1     root"
+        ;;
+esac
+"#,
+        );
+        let p_ctx = podman.ctx();
+
+        let rows = top("rank0", &[], Some(&p_ctx))?;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].pid, Some(1));
+        assert_eq!(rows[0].user.as_deref(), Some("root"));
+        assert_eq!(rows[0].comm, None);
+        assert_eq!(rows[0].args.as_deref(), Some("mpi_app --ranks 4 -v"));
+        assert_eq!(rows[0].cpu_percent, Some(98.765));
+        assert_eq!(rows[0].elapsed, Some(Duration::from_millis(3_723_500)));
+        assert_eq!(rows[0].host_pid, Some(41234));
+        assert_eq!(rows[1].host_pid, None);
+        assert_eq!(rows[1].fields["hpid"], "?");
+        assert_eq!(rows[1].args.as_deref(), Some("sleep 3600"));
+
+        let rows = top("rank0", &["pid", "user"], Some(&p_ctx))?;
+        assert_eq!(
+            rows,
+            [TopRow {
+                pid: Some(1),
+                user: Some(String::from("root")),
+                fields: BTreeMap::from([
+                    (String::from("pid"), String::from("1")),
+                    (String::from("user"), String::from("root")),
+                ]),
+                ..Default::default()
+            }]
+        );
+
+        // A name with a space, as set by the process
+        let rows = top("rank0", &["pid", "comm"], Some(&p_ctx))?;
+        assert_eq!(rows[0].comm.as_deref(), Some("mpi app"));
+        assert!(top("rank0", &["comm", "pid"], Some(&p_ctx)).is_err());
+        assert!(top("rank0", &["pid", "args", "etime"], Some(&p_ctx)).is_err());
+
+        assert_eq!(
+            podman.calls(),
+            [
+                "top rank0 pid user pcpu etime hpid args",
+                "top rank0 pid user",
+                "top rank0 pid comm"
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use crate::state::read_pidfile;
+use crate::{ContainerCtx, PodmanCtx, checked_output, commands, inspect};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FIRST_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+// Conditions accepted by `podman wait --condition`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitCondition {
+    Running,
+    Stopped,
+    Exited,
+    Healthy,
+    Unhealthy,
+}
+
+impl WaitCondition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Stopped => "stopped",
+            Self::Exited => "exited",
+            Self::Healthy => "healthy",
+            Self::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+// Block until the container meets one of `conditions` (stopped if empty) and return its exit
+// code. Podman reports -1 when the condition met does not end the container, e.g. healthy.
+pub fn wait(
+    name: &str,
+    conditions: &[WaitCondition],
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<i32> {
+    let output = checked_output(
+        &mut commands::wait(name, conditions, podman_ctx),
+        "podman wait",
+    )?;
+    let code = str::from_utf8(&output.stdout)?.trim();
+    Ok(code.parse()?)
+}
+
+// Wait until the container is running and return its PID
+pub fn wait_for_running(
+    name: &str,
+    timeout: Duration,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<u32> {
+    let mut last_error = String::new();
+    let pid = poll(timeout, || {
+        let output = inspect(name, Some("{{.State.Status}} {{.State.Pid}}"), podman_ctx);
+        if !output.status.success() {
+            // The container may not be created yet
+            last_error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Ok(None);
+        }
+
+        let state = str::from_utf8(&output.stdout)?.trim().to_string();
+        match state.split_once(' ') {
+            Some(("running", pid)) => Ok(Some(pid.parse::<u32>()?).filter(|&pid| pid > 0)),
+            Some(("exited" | "stopped", _)) => {
+                anyhow::bail!("container {name} stopped before it could be seen running")
+            }
+            _ => Ok(None),
+        }
+    })?;
+
+    pid.ok_or_else(|| {
+        let mut err = format!("timed out after {timeout:?} waiting for container {name} to run");
+        if !last_error.is_empty() {
+            err.push_str(": ");
+            err.push_str(&last_error);
+        }
+        anyhow::anyhow!(err)
+    })
+}
+
+// Wait until the PID of the container is available. The pidfile is read when
+// `ContainerCtx::pidfile` is set, otherwise `podman inspect` is polled.
+pub fn wait_for_pid(
+    c_ctx: &ContainerCtx,
+    timeout: Duration,
+    podman_ctx: Option<&PodmanCtx>,
+) -> anyhow::Result<u32> {
+    let Some(pidfile) = c_ctx.pidfile.as_deref() else {
+        return wait_for_running(&c_ctx.name, timeout, podman_ctx);
+    };
+
+    // Missing or still empty while conmon sets the container up
+    let pid = poll(timeout, || {
+        Ok(read_pidfile(pidfile).ok().filter(|&pid| pid > 0))
+    })?;
+    pid.ok_or_else(|| {
+        anyhow::anyhow!(
+            "timed out after {timeout:?} waiting for pidfile {}",
+            pidfile.display()
+        )
+    })
+}
+
+// Call `attempt` with exponential backoff until it yields a value or `timeout` expires
+pub(crate) fn poll<T>(
+    timeout: Duration,
+    mut attempt: impl FnMut() -> anyhow::Result<Option<T>>,
+) -> anyhow::Result<Option<T>> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = FIRST_BACKOFF;
+
+    loop {
+        if let Some(val) = attempt()? {
+            return Ok(Some(val));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(backoff.min(deadline - now));
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::FakePodman;
+    use std::fs;
+
+    #[test]
+    fn test_wait_for_pid_from_pidfile() -> anyhow::Result<()> {
+        // Fails if podman inspect were used
+        let podman = FakePodman::new("wait", "exit 125\n");
+        let p_ctx = podman.ctx();
+        let pidfile = podman.dir().join("pidfile");
+        let c_ctx = ContainerCtx {
+            name: String::from("sarus_wait_test"),
+            interactive: false,
+            detach: true,
+            set_env: true,
+            pidfile: Some(pidfile.clone()),
+            ..Default::default()
+        };
+
+        let err = wait_for_pid(&c_ctx, Duration::from_millis(50), Some(&p_ctx)).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+
+        let writer = thread::spawn({
+            let pidfile = pidfile.clone();
+            move || {
+                thread::sleep(Duration::from_millis(100));
+                fs::write(pidfile, "4242\n").unwrap();
+            }
+        });
+        let pid = wait_for_pid(&c_ctx, Duration::from_secs(5), Some(&p_ctx))?;
+        assert_eq!(pid, 4242);
+
+        writer.join().unwrap();
+        Ok(())
+    }
+}
@@ -1,10 +1,9 @@
 use bstr::ByteSlice;
-use raster;
 use sarus_suite_podman_driver::{self as pmd, ContainerCtx};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 #[test]
 fn test_run_output() {
@@ -24,6 +23,7 @@ fn test_run_from_edf_output() {
         detach: false,
         set_env: true,
         pidfile: None,
+        ..Default::default()
     };
 
     let edf_path = std::env::current_dir()
@@ -31,7 +31,8 @@ fn test_run_from_edf_output() {
         .join("tests/edf/alpine.toml");
     let edf =
         raster::render(edf_path.to_string_lossy().into_owned()).expect("Failed to render EDF");
-    let out = pmd::run_from_edf_output(&edf, None, &ctx, ["grep", "PRETTY", "/etc/os-release"]);
+    let (out, _) =
+        pmd::run_from_edf_output(&edf, None, &ctx, ["grep", "PRETTY", "/etc/os-release"]);
     assert!(
         out.stdout
             .as_slice()
@@ -47,6 +48,7 @@ fn test_run_from_edf_detached_output() -> anyhow::Result<()> {
         detach: true,
         set_env: true,
         pidfile: Some(PathBuf::from("/tmp/sarus-edf-test-pidfile")),
+        ..Default::default()
     };
 
     let edf_path = std::env::current_dir()
@@ -54,7 +56,7 @@ fn test_run_from_edf_detached_output() -> anyhow::Result<()> {
         .join("tests/edf/alpine.toml");
     let edf =
         raster::render(edf_path.to_string_lossy().into_owned()).expect("Failed rendering EDF");
-    let out = pmd::run_from_edf_output(&edf, None, &ctx, ["sleep", "3"]);
+    let (out, _) = pmd::run_from_edf_output(&edf, None, &ctx, ["sleep", "3"]);
 
     let run_stdout = str::from_utf8(&out.stdout)?;
     let run_stdout = String::from(run_stdout.trim());
@@ -136,6 +138,7 @@ fn test_get_container_pid_from_pidfile() -> anyhow::Result<()> {
         detach: true,
         set_env: true,
         pidfile: Some(PathBuf::from("/tmp/sarus-edf-test-pidfile")),
+        ..Default::default()
     };
 
     let edf_path = std::env::current_dir()
@@ -143,7 +146,7 @@ fn test_get_container_pid_from_pidfile() -> anyhow::Result<()> {
         .join("tests/edf/alpine.toml");
     let edf =
         raster::render(edf_path.to_string_lossy().into_owned()).expect("Failed rendering EDF");
-    let run = pmd::run_from_edf_output(&edf, None, &ctx, ["sleep", "5"]);
+    let (run, _) = pmd::run_from_edf_output(&edf, None, &ctx, ["sleep", "5"]);
     assert!(run.status.success(), "Could not run container!");
 
     let mut cnt_pidfile = File::open(ctx.pidfile.as_ref().unwrap())?;
@@ -158,3 +161,103 @@ fn test_get_container_pid_from_pidfile() -> anyhow::Result<()> {
     assert!(!std::fs::exists(ctx.pidfile.unwrap())?);
     Ok(())
 }
+
+#[test]
+fn test_list_images() -> anyhow::Result<()> {
+    let image = "alpine:3.22";
+    if !pmd::image_exists(image, None) {
+        pmd::pull(image, None);
+    }
+
+    let images = pmd::list_images(None)?;
+    let alpine = images
+        .iter()
+        .find(|img| img.names.iter().any(|n| n.ends_with("/alpine:3.22")))
+        .expect("Pulled image not listed");
+    assert_eq!(alpine.store, pmd::ImageStore::Graphroot);
+    Ok(())
+}
+
+#[test]
+fn test_get_container_pid_from_storage() -> anyhow::Result<()> {
+    let cnt_name = String::from("sarus_get_cnt_pid_storage_test");
+    let run = pmd::run_output(
+        [
+            "--rm",
+            "--detach",
+            "--name",
+            &cnt_name,
+            "alpine:3.22",
+            "sleep",
+            "5",
+        ],
+        None,
+    );
+    assert!(run.status.success(), "Could not run container!");
+
+    let t0 = Instant::now();
+    let storage_pid = pmd::get_container_pid_from_storage(&cnt_name, None, None)?;
+    let tend = t0.elapsed();
+    println!(
+        "pid from storage took: {:.3} ms",
+        tend.as_secs_f64() * 1_000.0
+    );
+
+    let inspect_pid = pmd::get_container_pid(&cnt_name, None)?;
+    assert_eq!(storage_pid, inspect_pid);
+    Ok(())
+}
+
+#[test]
+fn test_wait_for_running_and_exit_code() -> anyhow::Result<()> {
+    let cnt_name = String::from("sarus_wait_test");
+    let run = pmd::run_output(
+        [
+            "--detach",
+            "--name",
+            &cnt_name,
+            "alpine:3.22",
+            "sh",
+            "-c",
+            "sleep 1; exit 3",
+        ],
+        None,
+    );
+    assert!(run.status.success(), "Could not run container!");
+
+    let pid = pmd::wait_for_running(&cnt_name, Duration::from_secs(10), None)?;
+    assert_eq!(pid, pmd::get_container_pid(&cnt_name, None)?);
+
+    let exit_code = pmd::wait(&cnt_name, &[pmd::WaitCondition::Exited], None)?;
+    assert_eq!(exit_code, 3);
+
+    pmd::rm(&cnt_name, None);
+    Ok(())
+}
+
+#[test]
+fn test_create_and_start_from_edf() -> anyhow::Result<()> {
+    let ctx = ContainerCtx {
+        name: String::from("sarus_create_test"),
+        set_env: true,
+        ..Default::default()
+    };
+
+    let edf_path = std::env::current_dir()
+        .unwrap()
+        .join("tests/edf/alpine.toml");
+    let edf =
+        raster::render(edf_path.to_string_lossy().into_owned()).expect("Failed rendering EDF");
+    let created = pmd::create_from_edf(&edf, None, &ctx, ["grep", "PRETTY", "/etc/os-release"])?;
+    assert!(!created.id.is_empty());
+    assert_eq!(created.name, ctx.name);
+
+    let out = pmd::start_attached_output(&created.name, None);
+    assert!(out.status.success());
+    assert!(
+        out.stdout
+            .as_slice()
+            .contains_str("PRETTY_NAME=\"Alpine Linux v3.22\"")
+    );
+    Ok(())
+}